@@ -1,39 +1,132 @@
-use clap::{App, Arg, SubCommand};
-use std::path::{Path, PathBuf};
+use clap::{App, Arg, ArgMatches, ErrorKind, SubCommand};
+use esycpy::watch::{WatchOptions, DEFAULT_DEBOUNCE};
+use esycpy::{HashAlgo, Job, JobOptions, OnEmpty, Operation};
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf, MAIN_SEPARATOR};
+use std::time::Duration;
 
-#[derive(Debug, PartialEq)]
-pub enum Operation {
-    Copy_,
-    Move,
-    Hardlink,
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct LogOptions {
+    /// none when there's no log file
+    pub file: Option<PathBuf>,
+    /// log file is written as one json object per line
+    pub json: bool,
+    /// terminal log level, -1 is quiet, every --verbose adds 1
+    pub verbosity: i64,
+}
+
+/// options of the whole run, not of single jobs
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct RunOptions {
+    /// changes of the run are recorded in a journal, so it can be undone.
+    /// it's on unless turned off
+    pub journal: bool,
+    /// journals of runs are kept here
+    pub journal_dir: PathBuf,
+    /// json file jobs and files done by an interrupted run are skipped. the
+    /// progress of every json file run is kept either way
+    pub resume: bool,
+    /// events of the run are written to stdout, one json object per line
+    pub events: bool,
+    /// stats of the run are written to this json file
+    pub report: Option<PathBuf>,
+    /// source files and dirs of the run are listed in this make style depfile
+    pub depfile: Option<PathBuf>,
+    /// touched when the run succeeds
+    pub stamp: Option<PathBuf>,
+    pub log: LogOptions,
 }
 
 #[derive(Debug, PartialEq)]
 pub enum ArgsType {
-    CmdLine {
-        op: Operation,
-        from: PathBuf,
-        to: PathBuf,
-    },
+    CmdLine(Job),
     Json {
         json_file: PathBuf,
         variables: Option<Vec<(String, String)>>,
+        opts: JobOptions,
+    },
+    Watch {
+        json_file: PathBuf,
+        variables: Option<Vec<(String, String)>>,
+        opts: JobOptions,
+        watch: WatchOptions,
+    },
+    Undo {
+        run: Option<String>,
     },
 }
 
-pub fn get_args() -> ArgsType {
+/// "$ESYCPY_DIR", else ".esycpy" in the home dir
+fn esycpy_dir() -> PathBuf {
+    if let Some(dir) = env::var_os("ESYCPY_DIR") {
+        return PathBuf::from(dir);
+    }
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+        .unwrap_or_default()
+        .join(".esycpy")
+}
+
+/// "$ESYCPY_JOURNAL_DIR", else "journal" in the esycpy dir
+fn default_journal_dir() -> PathBuf {
+    match env::var_os("ESYCPY_JOURNAL_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None => esycpy_dir().join("journal"),
+    }
+}
+
+pub fn get_args() -> (ArgsType, RunOptions) {
     let arg_from = Arg::with_name("from")
         .short("s")
         .long("from")
         .takes_value(true)
         .value_name("source_path")
-        .required(true);
+        .multiple(true)
+        .number_of_values(1)
+        .required_unless("sources");
+    let arg_sources = Arg::with_name("sources")
+        .multiple(true)
+        .value_name("SOURCE")
+        .required_unless("from");
     let arg_to = Arg::with_name("to")
         .short("d")
         .long("to")
         .takes_value(true)
-        .value_name("destination_path")
-        .required(true);
+        .value_name("destination_path");
+    let arg_target = Arg::with_name("target")
+        .short("t")
+        .long("target-directory")
+        .takes_value(true)
+        .value_name("destination_dir")
+        .conflicts_with("to");
+    let arg_json = Arg::with_name("json_file")
+        .short("j")
+        .long("json")
+        .takes_value(true)
+        .validator_os(|p| -> Result<(), OsString> {
+            let jfile = Path::new(p).canonicalize();
+            if jfile.is_ok() {
+                Ok(())
+            } else {
+                Err(OsString::from("json file path isn't correct."))
+            }
+        })
+        .value_name("JSON_FILE_PATH");
+    let arg_variables = Arg::with_name("variables")
+        .short("v")
+        .long("variables")
+        .requires("json_file")
+        .min_values(1)
+        .validator(|v| -> Result<(), String> {
+            if v.contains('=') {
+                Ok(())
+            } else {
+                Err(String::from("assignment operator not found. check help."))
+            }
+        })
+        .value_name("VARIABLE_NAME_VALUE_PAIR");
     let app = App::new("EsyCpy")
         .author("Rajat Rajput <rajputrajat@gmail.com")
         .about("copy, move files and create hardlinks with ease.")
@@ -42,70 +135,248 @@ pub fn get_args() -> ArgsType {
             SubCommand::with_name("copy")
                 .about("copy file/dir from source to destination")
                 .arg(arg_from.clone())
-                .arg(arg_to.clone()),
+                .arg(arg_sources.clone())
+                .arg(arg_to.clone())
+                .arg(arg_target.clone()),
         )
         .subcommand(
             SubCommand::with_name("move")
                 .about("move file/dir from source to destination")
                 .arg(arg_from.clone())
-                .arg(arg_to.clone()),
+                .arg(arg_sources.clone())
+                .arg(arg_to.clone())
+                .arg(arg_target.clone()),
         )
         .subcommand(
             SubCommand::with_name("hardlink")
                 .about("create hardlinks of file/s from source to destination")
                 .arg(arg_from)
-                .arg(arg_to),
+                .arg(arg_sources)
+                .arg(arg_to)
+                .arg(arg_target),
+        )
+        .subcommand(
+            SubCommand::with_name("watch")
+                .about("run jobs of a json file, then apply changes of their sources")
+                .arg(arg_json.clone().required(true))
+                .arg(arg_variables.clone())
+                .arg(Arg::with_name("mirror").long("mirror"))
+                .arg(
+                    Arg::with_name("debounce")
+                        .long("debounce")
+                        .takes_value(true)
+                        .validator(|v| -> Result<(), String> {
+                            v.parse::<u64>()
+                                .map(|_| ())
+                                .map_err(|_| String::from("debounce must be milliseconds"))
+                        })
+                        .value_name("MS"),
+                ),
         )
+        .subcommand(
+            SubCommand::with_name("undo")
+                .about("revert the changes of the last run, or of the given run")
+                .arg(
+                    Arg::with_name("run")
+                        .long("run")
+                        .takes_value(true)
+                        .value_name("RUN_ID"),
+                ),
+        )
+        .arg(arg_json)
+        .arg(arg_variables)
         .arg(
-            Arg::with_name("json_file")
-                .short("j")
-                .long("json")
-                .takes_value(true)
-                .validator(|p| -> Result<(), String> {
-                    let jfile = Path::new(p.as_str()).canonicalize();
-                    if jfile.is_ok() {
-                        Ok(())
-                    } else {
-                        Err(String::from("json file path isn't correct."))
-                    }
-                })
-                .value_name("JSON_FILE_PATH"),
-        )
-        .arg(
-            Arg::with_name("variables")
-                .short("v")
-                .long("variables")
+            Arg::with_name("resume")
+                .long("resume")
                 .requires("json_file")
-                .min_values(1)
-                .validator(|v| -> Result<(), String> {
-                    if v.contains('=') {
-                        Ok(())
-                    } else {
-                        Err(String::from("assignment operator not found. check help."))
-                    }
-                })
-                .value_name("VARIABLE_NAME_VALUE_PAIR"),
+                .help("carry on with the jobs of an interrupted run"),
+        )
+        .arg(
+            Arg::with_name("verify")
+                .long("verify")
+                .global(true)
+                .help("verify copied/moved files with a checksum"),
+        )
+        .arg(
+            Arg::with_name("hash")
+                .long("hash")
+                .global(true)
+                .takes_value(true)
+                .possible_values(&["blake3", "sha256", "xxhash"])
+                .value_name("HASH"),
+        )
+        .arg(
+            Arg::with_name("no_atomic")
+                .long("no-atomic")
+                .global(true)
+                .help("write destination files in place, not via temp file and rename"),
+        )
+        .arg(
+            Arg::with_name("on_empty")
+                .long("on-empty")
+                .global(true)
+                .takes_value(true)
+                .possible_values(&["ok", "warn", "error"])
+                .value_name("POLICY"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .global(true)
+                .takes_value(true)
+                .possible_values(&["text", "json"])
+                .value_name("FORMAT"),
+        )
+        .arg(
+            Arg::with_name("events")
+                .long("events")
+                .global(true)
+                .takes_value(true)
+                .possible_values(&["ndjson"])
+                .value_name("FORMAT"),
+        )
+        .arg(
+            Arg::with_name("report")
+                .long("report")
+                .global(true)
+                .takes_value(true)
+                .value_name("FILE"),
+        )
+        .arg(
+            Arg::with_name("depfile")
+                .long("depfile")
+                .global(true)
+                .takes_value(true)
+                .value_name("FILE"),
+        )
+        .arg(
+            Arg::with_name("stamp")
+                .long("stamp")
+                .global(true)
+                .takes_value(true)
+                .value_name("FILE"),
+        )
+        .arg(
+            Arg::with_name("log_file")
+                .long("log-file")
+                .global(true)
+                .takes_value(true)
+                .value_name("PATH"),
+        )
+        .arg(
+            Arg::with_name("no_log_file")
+                .long("no-log-file")
+                .global(true)
+                .conflicts_with("log_file"),
+        )
+        .arg(
+            Arg::with_name("log_format")
+                .long("log-format")
+                .global(true)
+                .takes_value(true)
+                .possible_values(&["text", "json"])
+                .value_name("FORMAT"),
+        )
+        .arg(
+            Arg::with_name("verbose")
+                .long("verbose")
+                .global(true)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .short("q")
+                .long("quiet")
+                .global(true)
+                .conflicts_with("verbose"),
+        )
+        .arg(
+            Arg::with_name("journal_dir")
+                .long("journal-dir")
+                .global(true)
+                .takes_value(true)
+                .value_name("DIR"),
+        )
+        .arg(
+            Arg::with_name("no_journal")
+                .long("no-journal")
+                .global(true)
+                .conflicts_with("journal_dir")
+                .help("don't record changes of this run, it can't be undone then"),
         );
     let matches = app.clone().get_matches();
-    let json_file_path = matches.value_of("json_file");
-    if let Some(json_file_path) = json_file_path {
-        let json_file = Path::new(json_file_path).to_owned();
-        if let Some(variables) = matches.values_of("variables") {
-            let variables = variables.fold(Vec::new(), |mut vec: Vec<(String, String)>, v| {
-                let name_value: Vec<&str> = (*v).split('=').collect();
-                vec.push((String::from(name_value[0]), String::from(name_value[1])));
-                vec
-            });
+    let opts = JobOptions {
+        verify: matches.is_present("verify"),
+        hash: matches
+            .value_of("hash")
+            .and_then(HashAlgo::from_name)
+            .unwrap_or_default(),
+        atomic: !matches.is_present("no_atomic"),
+        on_empty: matches
+            .value_of("on_empty")
+            .and_then(OnEmpty::from_name)
+            .unwrap_or_default(),
+        ..Default::default()
+    };
+    let run_opts = RunOptions {
+        journal: !matches.is_present("no_journal"),
+        journal_dir: matches
+            .value_of_os("journal_dir")
+            .map(PathBuf::from)
+            .unwrap_or_else(default_journal_dir),
+        resume: matches.is_present("resume"),
+        events: matches.value_of("output") == Some("json") || matches.is_present("events"),
+        report: matches.value_of_os("report").map(PathBuf::from),
+        depfile: matches.value_of_os("depfile").map(PathBuf::from),
+        stamp: matches.value_of_os("stamp").map(PathBuf::from),
+        log: LogOptions {
+            file: if matches.is_present("no_log_file") {
+                None
+            } else {
+                Some(
+                    matches
+                        .value_of_os("log_file")
+                        .map(PathBuf::from)
+                        .unwrap_or_else(|| esycpy_dir().join("esycpy.log")),
+                )
+            },
+            json: matches.value_of("log_format") == Some("json"),
+            verbosity: if matches.is_present("quiet") {
+                -1
+            } else {
+                matches.occurrences_of("verbose") as i64
+            },
+        },
+    };
+    if let Some(json_file) = matches.value_of_os("json_file") {
+        (
             ArgsType::Json {
-                json_file,
-                variables: Some(variables),
-            }
-        } else {
-            ArgsType::Json {
-                json_file,
-                variables: None,
-            }
-        }
+                json_file: PathBuf::from(json_file),
+                variables: variables_of(&matches),
+                opts,
+            },
+            run_opts,
+        )
+    } else if let Some(watch_matches) = matches.subcommand_matches("watch") {
+        let watch = WatchOptions {
+            mirror: watch_matches.is_present("mirror"),
+            debounce: watch_matches
+                .value_of("debounce")
+                .map(|ms| Duration::from_millis(ms.parse().unwrap()))
+                .unwrap_or(DEFAULT_DEBOUNCE),
+        };
+        (
+            ArgsType::Watch {
+                json_file: PathBuf::from(watch_matches.value_of_os("json_file").unwrap()),
+                variables: variables_of(watch_matches),
+                opts,
+                watch,
+            },
+            run_opts,
+        )
+    } else if let Some(undo_matches) = matches.subcommand_matches("undo") {
+        let run = undo_matches.value_of("run").map(String::from);
+        (ArgsType::Undo { run }, run_opts)
     } else {
         let subcommand = match matches.subcommand_name() {
             Some("copy") => ("copy", Operation::Copy_),
@@ -117,19 +388,64 @@ pub fn get_args() -> ArgsType {
             }
         };
         if let Some(subcommand_matches) = matches.subcommand_matches(subcommand.0) {
-            let source = subcommand_matches.value_of("from").unwrap();
-            let destination = subcommand_matches.value_of("to").unwrap();
-            ArgsType::CmdLine {
-                op: subcommand.1,
-                from: PathBuf::from(source),
-                to: PathBuf::from(destination),
-            }
+            let mut positionals: Vec<&OsStr> = subcommand_matches
+                .values_of_os("sources")
+                .into_iter()
+                .flatten()
+                .collect();
+            // with neither -d nor -t, the last positional is the destination, like cp/mv
+            let destination = if let Some(to) = subcommand_matches.value_of_os("to") {
+                PathBuf::from(to)
+            } else if let Some(target) = subcommand_matches.value_of_os("target") {
+                // trailing separator marks the destination as a dir
+                let mut target = target.to_owned();
+                target.push(MAIN_SEPARATOR.to_string());
+                PathBuf::from(target)
+            } else if !positionals.is_empty()
+                && (positionals.len() > 1 || subcommand_matches.is_present("from"))
+            {
+                PathBuf::from(positionals.pop().unwrap())
+            } else {
+                clap::Error::with_description(
+                    "destination is missing. give it as last path, or with -d or -t",
+                    ErrorKind::MissingRequiredArgument,
+                )
+                .exit();
+            };
+            let sources: Vec<PathBuf> = subcommand_matches
+                .values_of_os("from")
+                .into_iter()
+                .flatten()
+                .chain(positionals)
+                .map(PathBuf::from)
+                .collect();
+            (
+                ArgsType::CmdLine(Job {
+                    op: subcommand.1,
+                    from: sources,
+                    to: destination,
+                    opts,
+                }),
+                run_opts,
+            )
         } else {
             panic!("neither json option is provided, nor cmdline option is used. use help command");
         }
     }
 }
 
+/// "name=value" pairs given with -v
+fn variables_of(matches: &ArgMatches) -> Option<Vec<(String, String)>> {
+    matches.values_of("variables").map(|variables| {
+        variables
+            .map(|v| {
+                let name_value: Vec<&str> = v.split('=').collect();
+                (String::from(name_value[0]), String::from(name_value[1]))
+            })
+            .collect()
+    })
+}
+
 const HELP: &str = r#"
 EsyCpy
 Rajat Rajput <rajputrajat@gmail.com>
@@ -138,10 +454,18 @@ copy, move and create hardlinks of files/dirs with ease
 USAGE:
     esycpy [SUBCOMMAND]
     esycpy [OPTIONS]
+    esycpy <copy|move|hardlink> SOURCE... DESTINATION
+    esycpy <copy|move|hardlink> -t DESTINATION_DIR SOURCE...
+    esycpy undo [--run RUN_ID]
+    esycpy watch -j JSON_FILE_PATH [--mirror] [--debounce MS]
 
 FLAGS:
     -h, --help      Prints help information
     -V, --version   Prints version information
+        --verbose   show more on the terminal, can be repeated: info, debug,
+                    trace. it has no short flag, -v is --variables.
+                    $ESYCPY_LOG (e.g. "debug") overrides these flags
+    -q, --quiet     show errors only, no progress or summary
 
 OPTIONS:
     -j, --json <JSON_FILE_PATH>
@@ -149,12 +473,167 @@ OPTIONS:
     -v, --variables <VARIABLE_NAME_VALUE_PAIR>...
                     these are optionally used in input json file,
                     multiple values can be given like this <var_name=var_value>
+        --resume    skip jobs and files which an interrupted run of the json
+                    file finished. every json file run keeps its progress in
+                    ".<json file name>.esycpy-state" next to the json file.
+                    large files are copied on from where they stopped
+        --verify    verify every copied file, and files moved across devices,
+                    with a checksum. a moved source is removed after it passes
+        --hash <HASH>
+                    checksum used by --verify: blake3 (default), sha256, xxhash.
+                    for json files, these two are defaults of all jobs
+        --no-atomic
+                    write destination files in place. by default they are
+                    written to a temp file which is renamed over the target
+        --on-empty <POLICY>
+                    when a source matches no files: "ok", "warn" (default)
+                    or "error". for json files, this is the default of jobs
+        --output <FORMAT>
+                    "text" (default) or "json". json is the same as
+                    --events ndjson
+        --events <FORMAT>
+                    write events of the run to stdout as "ndjson", one json
+                    object per line with an "event" field: job_started,
+                    file_planned, file_transferred, file_skipped, error,
+                    job_finished and run_summary
+        --report <FILE>
+                    write stats of every job and of the whole run to a json
+                    file: files matched, copied, linked, moved, skipped,
+                    overwritten and failed, bytes, time and slowest files.
+                    the summary is also shown at the end of every run
+        --journal-dir <DIR>
+                    every run records its changes here, so it can be undone.
+                    default is $ESYCPY_JOURNAL_DIR or <esycpy dir>/journal.
+                    the esycpy dir is $ESYCPY_DIR, or ~/.esycpy by default.
+                    overwritten files are backed up in it as hardlinks, last
+                    10 runs are kept. files on another file system than the
+                    journal aren't backed up, their overwrite can't be undone
+        --no-journal
+                    don't record changes, the run can't be undone
+        --depfile <FILE>
+                    write a make style depfile, for make, ninja or cmake custom
+                    commands. it lists the json files, and every source file
+                    and dir the jobs read, so new files in a globbed dir are
+                    noticed too. its target is the --stamp file, or else the
+                    destinations of the jobs
+        --stamp <FILE>
+                    touch this file when all jobs succeed
+        --log-file <PATH>
+                    everything is logged here, default is
+                    <esycpy dir>/esycpy.log. a file over 10 MiB is rotated,
+                    3 old ones are kept
+        --no-log-file
+                    don't write a log file
+        --log-format <FORMAT>
+                    log file format: "text" (default) or "json", one json
+                    object per line with time, level, target and message
+
+JSON FILE FIELDS:
+    variables_in_use    variables used in this file, written as "{var_name}"
+    include             other json files whose variables and jobs are merged
+                        into this one. relative paths are resolved against
+                        the including file, variables can be used in paths
+    base_dir            relative job paths are resolved against this dir.
+                        default is the json file's dir, "cwd" means current
+                        working dir, other paths are relative to json file.
+                        "~" at start of any path is the home dir
+    defaults            options inherited by every job that doesn't set them
+    jobs                list of jobs with "src", "dst" and these options.
+                        "src" can also be an array of sources, then dirs
+                        are placed inside "dst" by their name:
+        todo            "copy", "move" or "hardlink"
+        on_conflict     "overwrite" (default), "skip" or "error" when the
+                        destination file already exists
+        exclude         file/dir name patterns to leave out, e.g. "*.tmp"
+        preserve_mtime  keep modification time of copied files
+        structure       "auto" (default), "keep" or "flatten". auto keeps
+                        the dir structure unless an extension is filtered
+        verify          true to verify transferred files with a checksum
+        hash            "blake3" (default), "sha256" or "xxhash"
+        atomic          false to write destination files in place
+        staged          build the whole destination dir next to it, then
+                        swap it into place once complete
+        keep_previous   with staged, keep the replaced dir as "<dst>.prev"
+        on_empty        "ok", "warn" (default) or "error" when a source
+                        matches no files
+        before          shell command run before the job, e.g.
+                        "mkdir -p {dst}". {dst} is the job's destination
+        after           shell command run after the job succeeded, e.g.
+                        "chmod -R a+r {dst}"
+        exec            copy only: shell command which writes every file
+                        instead of copying it, e.g. "cwebp {src} -o {dst}".
+                        {src} and {dst} are the file's paths, they're also
+                        in $ESYCPY_SRC and $ESYCPY_DST. commands run in the
+                        current dir, their output is logged and a non-zero
+                        exit fails the job
+        render          copy only: true to replace "{var_name}" in contents
+                        of text files with the json file's variables, e.g.
+                        for a setup.txt naming the output dir
+        render_extensions
+                        extensions of files to render, default is txt, xml,
+                        json, ini, cfg, conf, config, yaml, yml, toml, html,
+                        htm, md, csv and props. others are copied as they are
+        render_strict   true to fail on a placeholder without a variable,
+                        instead of keeping it
+        eol             copy only: line endings of text files, "lf", "crlf"
+                        or "native". they're kept when not given
+        strip_bom       copy only: true to remove a utf-8 byte order mark
+                        at the start of text files
+        trim_trailing_whitespace
+                        copy only: true to remove spaces and tabs at the
+                        end of lines of text files
+        text_extensions extensions of text files for eol, strip_bom and
+                        trim_trailing_whitespace. when not given, files
+                        which start with utf-8 without NUL bytes are text
+        rename          rules which rename files placed from dirs and
+                        filters, applied in order. a rule is "lower",
+                        "upper", a regex like
+                        { "match": "(.*)_hi\\.png", "to": "$1.png" },
+                        or a template of {stem}, {ext}, {parent} (source
+                        dir name), {index} (counting files from 1 in order
+                        of their paths) and {mtime:%Y-%m-%d}, like
+                        "{stem}_{index}.{ext}". a single rule needn't be in
+                        an array
+
+    "dst" can have {mtime:<format>}, {ext} and {size_bucket} of each file,
+    e.g. "/archive/{mtime:%Y}/{mtime:%m}/{ext}" sorts files into year, month
+    and extension dirs. {mtime} is {mtime:%Y-%m-%d}, size buckets are "tiny"
+    below 100 KiB, "small" below 1 MiB, "medium" below 100 MiB, "large" below
+    1 GiB, else "huge". a single file source is placed inside "dst" too. it
+    doesn't work with staged
 
 SUBCOMMANDS:
     copy            copy file/dir from source to destination
     hardlink        create hardlinks of file/s from source to destination
     help            Prints this message or the help of the given subcommand(s)
     move            move file/dir from source to destination
+    undo            revert the changes of the last run, or of the given run
+    watch           run jobs of a json file, then apply changes of their
+                    sources as they happen: changed and new files are
+                    transferred again, renamed files are renamed in the
+                    destination. hooks of a job run around its changes, and
+                    a staged job runs again. the json file is watched too.
+                    --verbose shows every applied change
+
+SUBCOMMAND OPTIONS:
+    -s, --from <source_path>...
+                    source file/dir/filter, can be repeated. sources can
+                    also be given as positional paths. with many sources,
+                    dirs are placed inside the destination by their name
+    -d, --to <destination_path>
+                    destination. without -d or -t, last positional path is
+                    the destination
+    -t, --target-directory <destination_dir>
+                    destination dir, all sources are placed inside it
+        --run <RUN_ID>
+                    undo: run to revert, its journal is in <journal dir>/RUN_ID
+    -j, --json <JSON_FILE_PATH>
+                    watch: json file of the jobs, -v gives its variables
+        --mirror    watch: files removed from sources are removed from
+                    destinations too
+        --debounce <MS>
+                    watch: changes are applied once sources didn't change for
+                    this long, default is 300
 
 EXAMPLES:
     USING INPUT JSON FILE:
@@ -214,4 +693,9 @@ EXAMPLES:
     3. Move xml files from this dir to destination dir
         > esycpy move -s c:/users/example/dir2/*.xml -d c:/users/example/dir_move_in_here
     4. Copy all ogg files recursively to destination dir
-        > esycpy copy /home/example/audios/**.ogg /home/example/only_oggs"#;
+        > esycpy copy /home/example/audios/**.ogg /home/example/only_oggs
+    5. Copy many sources into one destination dir
+        > esycpy copy -s c:/users/example/a.txt -s c:/users/example/dir3 -d c:/users/example/out
+        > esycpy copy c:/users/example/a.txt c:/users/example/dir3/*.png -d c:/users/example/out
+    6. Copy files into a destination dir
+        > esycpy copy -t c:/users/example/out c:/users/example/b.txt c:/users/example/dir1/f1.txt"#;
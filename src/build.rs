@@ -0,0 +1,150 @@
+//! runs a json job file from a cargo build script:
+//!
+//! ```no_run
+//! // in main() of build.rs
+//! esycpy::build::job_file("assets.json")
+//!     .bin_dir("OutDir")
+//!     .run()
+//!     .unwrap();
+//! ```
+//!
+//! cargo re-runs the build script when a json file, or a source file or dir of
+//! its jobs changes. so new, changed and removed assets are picked up, and
+//! nothing is copied again when they didn't change.
+
+use crate::depfile;
+use crate::job::JobOptions;
+use crate::json_parser::load_job_file;
+use crate::run::Run;
+use crate::stats::Stats;
+use anyhow::Result;
+use std::env;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// jobs of a json file, with the variables it uses
+#[derive(Debug)]
+pub struct BuildJobs {
+    json_file: PathBuf,
+    variables: Vec<(String, String)>,
+}
+
+/// a relative `json_file` is resolved against the package dir, like all
+/// paths of a build script
+pub fn job_file<P: Into<PathBuf>>(json_file: P) -> BuildJobs {
+    BuildJobs {
+        json_file: json_file.into(),
+        variables: Vec::new(),
+    }
+}
+
+impl BuildJobs {
+    pub fn variable<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Self {
+        self.variables.push((name.into(), value.into()));
+        self
+    }
+
+    /// sets variable `name` to OUT_DIR of the build script
+    pub fn out_dir(self, name: &str) -> Self {
+        let out_dir = BuildJobs::env_out_dir();
+        self.variable(name, out_dir.to_string_lossy())
+    }
+
+    /// sets variable `name` to the dir of the built binary, like "target/debug"
+    pub fn bin_dir(self, name: &str) -> Self {
+        let bin_dir = bin_dir_of(&BuildJobs::env_out_dir());
+        self.variable(name, bin_dir.to_string_lossy())
+    }
+
+    fn env_out_dir() -> PathBuf {
+        PathBuf::from(env::var_os("OUT_DIR").expect("OUT_DIR isn't set, run it from build.rs"))
+    }
+
+    /// runs all jobs, after telling cargo what they read
+    pub fn run(self) -> Result<Stats> {
+        self.run_with(&mut io::stdout())
+    }
+
+    fn run_with(self, out: &mut dyn Write) -> Result<Stats> {
+        let variables = if self.variables.is_empty() {
+            None
+        } else {
+            Some(self.variables)
+        };
+        let job_file = load_job_file(&self.json_file, variables, &JobOptions::default())?;
+        for input in depfile::inputs(&job_file.files, &job_file.jobs)? {
+            writeln!(out, "cargo:rerun-if-changed={}", input.display())?;
+        }
+        let mut run = Run::new();
+        run.jobs(job_file.jobs)?;
+        Ok(run.report().total.clone())
+    }
+}
+
+/// OUT_DIR is "<bin dir>/build/<package>-<hash>/out"
+fn bin_dir_of(out_dir: &Path) -> PathBuf {
+    out_dir
+        .ancestors()
+        .nth(3)
+        .expect("OUT_DIR isn't inside a target dir")
+        .to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn bin_dir_found() {
+        assert_eq!(
+            bin_dir_of(Path::new("/p/target/debug/build/game-0123abcd/out")),
+            Path::new("/p/target/debug")
+        );
+    }
+
+    #[test]
+    fn build_jobs_run() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let base = tmp_dir.path().canonicalize()?;
+        fs::create_dir_all(base.join("assets").join("sounds"))?;
+        fs::write(base.join("assets").join("a.png"), "a")?;
+        fs::write(base.join("assets").join("sounds").join("b.ogg"), "b")?;
+        let json_file = base.join("assets.json");
+        fs::write(
+            &json_file,
+            r#"{
+                "variables_in_use": ["{OutDir}"],
+                "jobs": [{ "todo": "copy", "src": "assets", "dst": "{OutDir}/assets" }]
+            }"#,
+        )?;
+        let out_dir = base.join("out");
+        let mut out = Vec::new();
+        let stats = job_file(&json_file)
+            .variable("OutDir", out_dir.to_string_lossy())
+            .run_with(&mut out)?;
+        assert_eq!(stats.copied, 2);
+        assert!(out_dir
+            .join("assets")
+            .join("sounds")
+            .join("b.ogg")
+            .is_file());
+        let rerun: Vec<PathBuf> = String::from_utf8(out)?
+            .lines()
+            .map(|l| PathBuf::from(l.strip_prefix("cargo:rerun-if-changed=").unwrap()))
+            .collect();
+        let assets = base.join("assets");
+        assert_eq!(
+            rerun,
+            [
+                json_file,
+                assets.clone(),
+                assets.join("a.png"),
+                assets.join("sounds"),
+                assets.join("sounds").join("b.ogg"),
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,292 @@
+use crate::journal::encoded_path;
+use crate::progress::Progress;
+use anyhow::Result;
+use log::{info, trace, warn};
+use serde::{Deserialize, Serialize};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// smaller files are copied again, when a run is resumed
+const RESUMABLE_SIZE: u64 = 64 * 1024 * 1024;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+struct Partial {
+    #[serde(with = "encoded_path")]
+    src: PathBuf,
+    len: u64,
+    mtime: SystemTime,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(tag = "record", rename_all = "snake_case")]
+enum Record {
+    /// identifies jobs of the run, a state of other jobs isn't resumed
+    Input {
+        fingerprint: String,
+    },
+    FileDone {
+        job: usize,
+        #[serde(with = "encoded_path")]
+        src: PathBuf,
+    },
+    JobDone {
+        job: usize,
+    },
+    /// a large file being copied to `part`, it's resumed when its source is unchanged
+    Partial {
+        #[serde(with = "encoded_path")]
+        part: PathBuf,
+        #[serde(flatten)]
+        partial: Partial,
+    },
+}
+
+/// progress of a json file run, kept in `.<json file name>.esycpy-state` next to
+/// it. the state file is removed once all jobs are done.
+#[derive(Debug)]
+pub struct Checkpoint {
+    path: PathBuf,
+    file: RefCell<Option<File>>,
+    job: Cell<usize>,
+    jobs_done: HashSet<usize>,
+    files_done: HashSet<(usize, PathBuf)>,
+    partials: HashMap<PathBuf, Partial>,
+    resumable_size: u64,
+}
+
+impl Checkpoint {
+    /// state left by an interrupted run is only read when resuming, a new run starts
+    /// it over. `input` describes the jobs, a resumed state must've been written for the same
+    pub fn open(json_file: &Path, input: &str, resume: bool) -> Result<Self> {
+        let mut name = OsString::from(".");
+        name.push(json_file.file_name().expect("file name must be present"));
+        name.push(".esycpy-state");
+        let path = json_file.with_file_name(name);
+        let fingerprint = blake3::hash(input.as_bytes()).to_hex().to_string();
+        let mut checkpoint = Self {
+            path: path.clone(),
+            file: RefCell::new(None),
+            job: Cell::new(0),
+            jobs_done: HashSet::new(),
+            files_done: HashSet::new(),
+            partials: HashMap::new(),
+            resumable_size: RESUMABLE_SIZE,
+        };
+        if resume && path.is_file() && checkpoint.load(&fingerprint)? {
+            info!("resuming from {:?}", path);
+            *checkpoint.file.get_mut() = Some(OpenOptions::new().append(true).open(&path)?);
+        } else {
+            if resume {
+                warn!("no state of an earlier run of these jobs, starting from the first job");
+            }
+            *checkpoint.file.get_mut() = Some(File::create(&path)?);
+            checkpoint.record(&Record::Input { fingerprint })?;
+        }
+        Ok(checkpoint)
+    }
+
+    /// returns false when the state is of other jobs
+    fn load(&mut self, fingerprint: &str) -> Result<bool> {
+        let mut lines = BufReader::new(File::open(&self.path)?).lines();
+        match lines.next().transpose()? {
+            Some(line)
+                if serde_json::from_str::<Record>(&line).ok()
+                    == Some(Record::Input {
+                        fingerprint: fingerprint.to_owned(),
+                    }) => {}
+            _ => return Ok(false),
+        }
+        for line in lines {
+            let line = line?;
+            match serde_json::from_str::<Record>(&line) {
+                Ok(Record::FileDone { job, src }) => {
+                    self.files_done.insert((job, src));
+                }
+                Ok(Record::JobDone { job }) => {
+                    self.jobs_done.insert(job);
+                }
+                Ok(Record::Partial { part, partial }) => {
+                    self.partials.insert(part, partial);
+                }
+                Ok(Record::Input { .. }) => {}
+                // an interrupted run can leave the last line half written
+                Err(e) => warn!("ignoring broken state line {:?}: {}", line, e),
+            }
+        }
+        Ok(true)
+    }
+
+    fn record(&self, record: &Record) -> Result<()> {
+        trace!("checkpoint: {:?}", record);
+        let mut file = self.file.borrow_mut();
+        let file = file.as_mut().unwrap();
+        writeln!(file, "{}", serde_json::to_string(record)?)?;
+        file.sync_data()?;
+        Ok(())
+    }
+
+    pub fn is_job_done(&self, job: usize) -> bool {
+        self.jobs_done.contains(&job)
+    }
+
+    /// files done from now on belong to `job`
+    pub fn start_job(&self, job: usize) {
+        self.job.set(job);
+    }
+
+    pub fn job_done(&self) -> Result<()> {
+        self.record(&Record::JobDone {
+            job: self.job.get(),
+        })
+    }
+
+    pub fn is_file_done(&self, src: &Path) -> bool {
+        self.files_done.contains(&(self.job.get(), src.to_owned()))
+    }
+
+    pub fn file_done(&self, src: &Path) -> Result<()> {
+        self.record(&Record::FileDone {
+            job: self.job.get(),
+            src: src.to_owned(),
+        })
+    }
+
+    /// copies `src` to `out`, a temp file of `dst` or `dst` itself. large files are
+    /// copied to a part file next to `dst` first, which a resumed run continues.
+    /// their bytes are shown in `progress` as they're copied.
+    pub fn copy(
+        &self,
+        src: &Path,
+        dst: &Path,
+        out: &Path,
+        progress: Option<&Progress>,
+    ) -> Result<()> {
+        let meta = fs::metadata(src)?;
+        if meta.len() < self.resumable_size {
+            fs::copy(src, out)?;
+            return Ok(());
+        }
+        let mut name = OsString::from(".");
+        name.push(dst.file_name().expect("file name must be present"));
+        name.push(".esycpy-part");
+        let part = std::path::absolute(dst.with_file_name(name))?;
+        let partial = Partial {
+            src: std::path::absolute(src)?,
+            len: meta.len(),
+            mtime: meta.modified()?,
+        };
+        let offset = match (self.partials.get(&part), fs::metadata(&part)) {
+            (Some(resumed), Ok(part_meta)) if *resumed == partial => {
+                part_meta.len().min(partial.len)
+            }
+            _ => {
+                self.record(&Record::Partial {
+                    part: part.clone(),
+                    partial,
+                })?;
+                0
+            }
+        };
+        trace!("copy {:?} to {:?} from offset {}", src, part, offset);
+        let mut src_file = File::open(src)?;
+        src_file.seek(SeekFrom::Start(offset))?;
+        let mut part_file = if offset > 0 {
+            OpenOptions::new().append(true).open(&part)?
+        } else {
+            File::create(&part)?
+        };
+        match progress {
+            Some(progress) => {
+                progress.advanced(offset);
+                progress.copy(&mut src_file, &mut part_file)?;
+            }
+            None => {
+                io::copy(&mut src_file, &mut part_file)?;
+            }
+        }
+        drop(part_file);
+        fs::set_permissions(&part, meta.permissions())?;
+        fs::rename(&part, out)?;
+        Ok(())
+    }
+
+    /// all jobs are done, nothing is left to resume
+    pub fn finish(&self) -> Result<()> {
+        self.file.borrow_mut().take();
+        fs::remove_file(&self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn resume_done_jobs_and_files() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let json_file = tmp_dir.path().join("jobs.json");
+        let checkpoint = Checkpoint::open(&json_file, "jobs", false)?;
+        checkpoint.start_job(0);
+        checkpoint.file_done(Path::new("a"))?;
+        checkpoint.job_done()?;
+        checkpoint.start_job(1);
+        checkpoint.file_done(Path::new("b"))?;
+        drop(checkpoint);
+        assert!(tmp_dir.path().join(".jobs.json.esycpy-state").is_file());
+
+        let checkpoint = Checkpoint::open(&json_file, "jobs", true)?;
+        assert!(checkpoint.is_job_done(0));
+        assert!(!checkpoint.is_job_done(1));
+        checkpoint.start_job(1);
+        assert!(checkpoint.is_file_done(Path::new("b")));
+        assert!(!checkpoint.is_file_done(Path::new("a")));
+        drop(checkpoint);
+
+        // state of other jobs isn't used
+        let checkpoint = Checkpoint::open(&json_file, "other jobs", true)?;
+        assert!(!checkpoint.is_job_done(0));
+        checkpoint.finish()?;
+        assert!(!tmp_dir.path().join(".jobs.json.esycpy-state").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn resume_partial_copy() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let json_file = tmp_dir.path().join("jobs.json");
+        let src = tmp_dir.path().join("src");
+        let dst = tmp_dir.path().join("dst");
+        let part = tmp_dir.path().join(".dst.esycpy-part");
+        fs::write(&src, "0123456789")?;
+        let mut checkpoint = Checkpoint::open(&json_file, "jobs", false)?;
+        checkpoint.resumable_size = 4;
+        checkpoint.copy(&src, &dst, &dst, None)?;
+        assert_eq!(fs::read_to_string(&dst)?, "0123456789");
+        assert!(!part.exists());
+        drop(checkpoint);
+
+        // the interrupted copy wrote 4 bytes, these aren't copied again
+        fs::write(&part, "abcd")?;
+        let mut checkpoint = Checkpoint::open(&json_file, "jobs", true)?;
+        checkpoint.resumable_size = 4;
+        checkpoint.copy(&src, &dst, &dst, None)?;
+        assert_eq!(fs::read_to_string(&dst)?, "abcd456789");
+        drop(checkpoint);
+
+        // a changed source is copied from the start
+        fs::write(&part, "abcd")?;
+        fs::write(&src, "98765432100")?;
+        let mut checkpoint = Checkpoint::open(&json_file, "jobs", true)?;
+        checkpoint.resumable_size = 4;
+        checkpoint.copy(&src, &dst, &dst, None)?;
+        assert_eq!(fs::read_to_string(&dst)?, "98765432100");
+        Ok(())
+    }
+}
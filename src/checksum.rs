@@ -0,0 +1,111 @@
+use crate::job::HashAlgo;
+use anyhow::{bail, Result};
+use log::trace;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use xxhash_rust::xxh3::Xxh3;
+
+const BUF_SIZE: usize = 64 * 1024;
+
+pub fn file_hash(path: &Path, algo: HashAlgo) -> Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    let mut buf = vec![0u8; BUF_SIZE];
+    let hash = match algo {
+        HashAlgo::Blake3 => {
+            let mut hasher = blake3::Hasher::new();
+            read_chunks(&mut file, &mut buf, |chunk| {
+                hasher.update(chunk);
+            })?;
+            hasher.finalize().as_bytes().to_vec()
+        }
+        HashAlgo::Sha256 => {
+            let mut hasher = Sha256::new();
+            read_chunks(&mut file, &mut buf, |chunk| hasher.update(chunk))?;
+            hasher.finalize().to_vec()
+        }
+        HashAlgo::XxHash => {
+            let mut hasher = Xxh3::new();
+            read_chunks(&mut file, &mut buf, |chunk| hasher.update(chunk))?;
+            hasher.digest128().to_be_bytes().to_vec()
+        }
+    };
+    Ok(hash)
+}
+
+/// hashes both files and fails when they differ
+pub fn verify(src: &Path, dst: &Path, algo: HashAlgo) -> Result<()> {
+    let src_hash = file_hash(src, algo)?;
+    let dst_hash = file_hash(dst, algo)?;
+    trace!("{:?} hash of {:?}: {:x?}", algo, src, src_hash);
+    if src_hash != dst_hash {
+        bail!(
+            "checksum mismatch, {} differs from {}",
+            dst.display(),
+            src.display()
+        );
+    }
+    Ok(())
+}
+
+fn read_chunks<F>(file: &mut File, buf: &mut [u8], mut f: F) -> Result<()>
+where
+    F: FnMut(&[u8]),
+{
+    loop {
+        let n = file.read(buf)?;
+        if n == 0 {
+            return Ok(());
+        }
+        f(&buf[..n]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn known_hashes() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let file = tmp_dir.path().join("abc");
+        fs::write(&file, "abc")?;
+        assert_eq!(
+            hex(&file_hash(&file, HashAlgo::Sha256)?),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            hex(&file_hash(&file, HashAlgo::Blake3)?),
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+        );
+        assert_eq!(
+            file_hash(&file, HashAlgo::XxHash)?,
+            xxhash_rust::xxh3::xxh3_128(b"abc").to_be_bytes().to_vec()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn verify_files() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let src = tmp_dir.path().join("src");
+        let dst = tmp_dir.path().join("dst");
+        fs::write(&src, vec![7u8; BUF_SIZE * 2 + 3])?;
+        fs::copy(&src, &dst)?;
+        for algo in &[HashAlgo::Blake3, HashAlgo::Sha256, HashAlgo::XxHash] {
+            verify(&src, &dst, *algo)?;
+        }
+        fs::write(&dst, vec![7u8; BUF_SIZE * 2])?;
+        for algo in &[HashAlgo::Blake3, HashAlgo::Sha256, HashAlgo::XxHash] {
+            assert!(verify(&src, &dst, *algo).is_err());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+//! make style depfiles and stamp files, so a build system like make, ninja or
+//! cmake runs jobs again only when their inputs change
+
+use crate::job::Job;
+use anyhow::Result;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// `json_files` and the source files and dirs of all `jobs`. it has to be
+/// called before the jobs run, as moved sources are gone after.
+pub fn inputs(json_files: &[PathBuf], jobs: &[Job]) -> Result<Vec<PathBuf>> {
+    let mut inputs = json_files.to_vec();
+    for job in jobs {
+        inputs.extend(job.inputs()?);
+    }
+    Ok(inputs)
+}
+
+/// writes "targets: inputs", one input per line
+pub fn write(path: &Path, targets: &[PathBuf], inputs: &[PathBuf]) -> Result<()> {
+    let targets: Vec<String> = targets.iter().map(|t| escape(t)).collect();
+    let mut text = format!("{}:", targets.join(" "));
+    for input in inputs {
+        text.push_str(" \\\n  ");
+        text.push_str(&escape(input));
+    }
+    text.push('\n');
+    create_parent(path)?;
+    fs::write(path, text)?;
+    Ok(())
+}
+
+/// creates the stamp file, or updates its mtime
+pub fn stamp(path: &Path) -> Result<()> {
+    create_parent(path)?;
+    File::create(path)?.set_modified(SystemTime::now())?;
+    Ok(())
+}
+
+fn create_parent(path: &Path) -> Result<()> {
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    Ok(())
+}
+
+/// spaces and "#" are escaped with a backslash, "$" is doubled
+fn escape(path: &Path) -> String {
+    let mut escaped = String::new();
+    for c in path.to_string_lossy().chars() {
+        match c {
+            ' ' | '#' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '$' => escaped.push_str("$$"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn depfile_written() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let path = tmp_dir.path().join("deps").join("out.d");
+        write(
+            &path,
+            &[PathBuf::from("out.stamp")],
+            &[
+                PathBuf::from("jobs.json"),
+                PathBuf::from("my assets"),
+                PathBuf::from("my assets/#1 $a.png"),
+            ],
+        )?;
+        assert_eq!(
+            fs::read_to_string(&path)?,
+            "out.stamp: \\\n  jobs.json \\\n  my\\ assets \\\n  my\\ assets/\\#1\\ $$a.png\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn stamp_touched() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let path = tmp_dir.path().join("out.stamp");
+        stamp(&path)?;
+        let old = SystemTime::now() - std::time::Duration::from_secs(60);
+        fs::OpenOptions::new()
+            .write(true)
+            .open(&path)?
+            .set_modified(old)?;
+        stamp(&path)?;
+        assert!(fs::metadata(&path)?.modified()? > old);
+        Ok(())
+    }
+}
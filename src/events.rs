@@ -0,0 +1,183 @@
+use crate::journal::encoded_path;
+use crate::stats::Stats;
+use serde::Serialize;
+use std::cell::Cell;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// what happened in a run, written as one json object per line on stdout
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    JobStarted {
+        op: &'static str,
+        #[serde(serialize_with = "paths")]
+        sources: Vec<PathBuf>,
+        #[serde(serialize_with = "encoded_path::serialize")]
+        dst: PathBuf,
+    },
+    FilePlanned {
+        op: &'static str,
+        #[serde(serialize_with = "encoded_path::serialize")]
+        src: PathBuf,
+        #[serde(serialize_with = "encoded_path::serialize")]
+        dst: PathBuf,
+        size: u64,
+    },
+    FileTransferred {
+        op: &'static str,
+        #[serde(serialize_with = "encoded_path::serialize")]
+        src: PathBuf,
+        #[serde(serialize_with = "encoded_path::serialize")]
+        dst: PathBuf,
+        size: u64,
+        duration_ms: u64,
+    },
+    FileSkipped {
+        op: &'static str,
+        #[serde(serialize_with = "encoded_path::serialize")]
+        src: PathBuf,
+        #[serde(serialize_with = "encoded_path::serialize")]
+        dst: PathBuf,
+        size: u64,
+        reason: &'static str,
+    },
+    Error {
+        op: &'static str,
+        #[serde(serialize_with = "encoded_path::serialize")]
+        src: PathBuf,
+        #[serde(serialize_with = "encoded_path::serialize")]
+        dst: PathBuf,
+        message: String,
+    },
+    JobFinished {
+        duration_ms: u64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    RunSummary {
+        jobs: usize,
+        failed_jobs: usize,
+        #[serde(flatten)]
+        stats: Stats,
+    },
+}
+
+fn paths<S: serde::Serializer>(paths: &[PathBuf], serializer: S) -> Result<S::Ok, S::Error> {
+    #[derive(Serialize)]
+    struct Encoded<'a>(#[serde(serialize_with = "encoded_path::serialize")] &'a PathBuf);
+    serializer.collect_seq(paths.iter().map(Encoded))
+}
+
+/// every event is tagged with the job it belongs to
+#[derive(Serialize)]
+struct Line<'a> {
+    #[serde(flatten)]
+    event: &'a Event,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    job: Option<usize>,
+}
+
+#[derive(Debug)]
+pub struct Events {
+    job: Cell<Option<usize>>,
+    job_start: Cell<Instant>,
+    jobs: Cell<usize>,
+    failed_jobs: Cell<usize>,
+}
+
+impl Events {
+    pub fn new() -> Self {
+        Self {
+            job: Cell::new(None),
+            job_start: Cell::new(Instant::now()),
+            jobs: Cell::new(0),
+            failed_jobs: Cell::new(0),
+        }
+    }
+
+    pub fn emit(&self, event: Event) {
+        let line = Line {
+            event: &event,
+            job: self.job.get(),
+        };
+        let mut stdout = io::stdout().lock();
+        // events are lost when stdout is gone, the run carries on
+        let _ = serde_json::to_writer(&mut stdout, &line);
+        let _ = writeln!(stdout);
+        let _ = stdout.flush();
+    }
+
+    pub fn job_started(&self, job: usize, event: Event) {
+        self.job.set(Some(job));
+        self.job_start.set(Instant::now());
+        self.jobs.set(self.jobs.get() + 1);
+        self.emit(event);
+    }
+
+    pub fn job_finished(&self, error: Option<String>) {
+        if error.is_some() {
+            self.failed_jobs.set(self.failed_jobs.get() + 1);
+        }
+        self.emit(Event::JobFinished {
+            duration_ms: millis(self.job_start.get().elapsed()),
+            error,
+        });
+        self.job.set(None);
+    }
+
+    pub fn summary(&self, total: &Stats) {
+        self.emit(Event::RunSummary {
+            jobs: self.jobs.get(),
+            failed_jobs: self.failed_jobs.get(),
+            stats: total.clone(),
+        });
+    }
+}
+
+impl Default for Events {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn millis(duration: Duration) -> u64 {
+    duration.as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_lines() -> serde_json::Result<()> {
+        let event = Event::FileSkipped {
+            op: "copy",
+            src: PathBuf::from("a/f.txt"),
+            dst: PathBuf::from("b/f.txt"),
+            size: 3,
+            reason: "exists",
+        };
+        assert_eq!(
+            serde_json::to_string(&Line {
+                event: &event,
+                job: Some(1)
+            })?,
+            r#"{"event":"file_skipped","op":"copy","src":"a/f.txt","dst":"b/f.txt","size":3,"reason":"exists","job":1}"#
+        );
+        let event = Event::JobStarted {
+            op: "move",
+            sources: vec![PathBuf::from("a"), PathBuf::from("b/*.txt")],
+            dst: PathBuf::from("c"),
+        };
+        assert_eq!(
+            serde_json::to_string(&Line {
+                event: &event,
+                job: None
+            })?,
+            r#"{"event":"job_started","op":"move","sources":["a","b/*.txt"],"dst":"c"}"#
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,85 @@
+use anyhow::{bail, Result};
+use log::{info, trace};
+use std::path::Path;
+use std::process::Command;
+
+/// runs `command` in the shell, after replacing "{name}" of every path in `paths`
+/// with the quoted path. paths are also given as env vars "ESYCPY_<NAME>".
+/// output is logged, a non-zero exit is an error.
+pub fn run(command: &str, paths: &[(&str, &Path)]) -> Result<()> {
+    let mut line = command.to_owned();
+    for (name, path) in paths {
+        line = line.replace(&format!("{{{}}}", name), &quote(path));
+    }
+    trace!("run hook: {}", line);
+    let mut shell = shell(&line);
+    for (name, path) in paths {
+        shell.env(format!("ESYCPY_{}", name.to_uppercase()), path);
+    }
+    let output = shell.output()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    for out in stdout.lines().chain(stderr.lines()) {
+        info!("{}: {}", command, out);
+    }
+    if !output.status.success() {
+        bail!(
+            "hook `{}` failed, {}: {}",
+            line,
+            output.status,
+            stderr.trim()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn shell(line: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(line);
+    shell
+}
+
+#[cfg(windows)]
+fn shell(line: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(line);
+    shell
+}
+
+#[cfg(unix)]
+fn quote(path: &Path) -> String {
+    format!("'{}'", path.to_string_lossy().replace('\'', r"'\''"))
+}
+
+#[cfg(windows)]
+fn quote(path: &Path) -> String {
+    format!("\"{}\"", path.to_string_lossy())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn paths_given() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let src = tmp_dir.path().join("it's a.txt");
+        let dst = tmp_dir.path().join("b.txt");
+        fs::write(&src, "text")?;
+        run(
+            "tr a-z A-Z < {src} > \"$ESYCPY_DST\"",
+            &[("src", &src), ("dst", &dst)],
+        )?;
+        assert_eq!(fs::read_to_string(&dst)?, "TEXT");
+        Ok(())
+    }
+
+    #[test]
+    fn failure_reported() {
+        let e = run("echo broken >&2; exit 3", &[]).unwrap_err();
+        assert!(format!("{:#}", e).contains("broken"));
+    }
+}
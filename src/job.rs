@@ -0,0 +1,618 @@
+use crate::operations::{FileOp, Paths};
+use crate::run::Run;
+use crate::stats::Stats;
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+/// what a job does with its sources
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Operation {
+    Copy_,
+    Move,
+    Hardlink,
+}
+
+impl Operation {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Operation::Copy_ => "copy",
+            Operation::Move => "move",
+            Operation::Hardlink => "hardlink",
+        }
+    }
+}
+
+/// what to do when a destination file already exists
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum OnConflict {
+    #[default]
+    Overwrite,
+    Skip,
+    Error,
+}
+
+/// how source files are laid out under the destination.
+/// `Auto` flattens only when an extension filter like `**.ogg` is used.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum Structure {
+    #[default]
+    Auto,
+    Keep,
+    Flatten,
+}
+
+/// hash used to verify transferred files
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum HashAlgo {
+    #[default]
+    Blake3,
+    Sha256,
+    XxHash,
+}
+
+impl HashAlgo {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "blake3" => Some(HashAlgo::Blake3),
+            "sha256" => Some(HashAlgo::Sha256),
+            "xxhash" => Some(HashAlgo::XxHash),
+            _ => None,
+        }
+    }
+}
+
+/// what to do when a source matches no files
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum OnEmpty {
+    Ok,
+    #[default]
+    Warn,
+    Error,
+}
+
+impl OnEmpty {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "ok" => Some(OnEmpty::Ok),
+            "warn" => Some(OnEmpty::Warn),
+            "error" => Some(OnEmpty::Error),
+            _ => None,
+        }
+    }
+}
+
+/// extensions rendered when a job doesn't list its own
+pub const DEFAULT_RENDER_EXTENSIONS: &[&str] = &[
+    "txt", "xml", "json", "ini", "cfg", "conf", "config", "yaml", "yml", "toml", "html", "htm",
+    "md", "csv", "props",
+];
+
+/// how copied text files get variables put in their contents
+#[derive(Debug, PartialEq, Clone)]
+pub struct Render {
+    /// values of "{name}" placeholders, the variables of the json file
+    pub variables: Vec<(String, String)>,
+    /// only files with these extensions are rendered, others are copied as
+    /// they are. compared without case and without a leading "."
+    pub extensions: Vec<String>,
+    /// a placeholder without a variable is an error, instead of being kept
+    pub strict: bool,
+}
+
+impl Default for Render {
+    fn default() -> Self {
+        Self {
+            variables: Vec::new(),
+            extensions: DEFAULT_RENDER_EXTENSIONS
+                .iter()
+                .map(|e| e.to_string())
+                .collect(),
+            strict: false,
+        }
+    }
+}
+
+impl Render {
+    pub fn renders(&self, path: &Path) -> bool {
+        has_extension(path, &self.extensions)
+    }
+}
+
+/// extensions are compared without case and without a leading "."
+pub(crate) fn has_extension(path: &Path, extensions: &[String]) -> bool {
+    let ext = match path.extension() {
+        Some(ext) => ext.to_string_lossy(),
+        None => return false,
+    };
+    extensions
+        .iter()
+        .any(|e| e.trim_start_matches('.').eq_ignore_ascii_case(&ext))
+}
+
+/// line ending written to text files
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Eol {
+    Lf,
+    Crlf,
+    /// crlf on windows, lf elsewhere
+    Native,
+}
+
+impl Eol {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "lf" => Some(Eol::Lf),
+            "crlf" => Some(Eol::Crlf),
+            "native" => Some(Eol::Native),
+            _ => None,
+        }
+    }
+}
+
+/// how contents of copied text files are normalized
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct TextFilter {
+    /// line endings are kept when not given
+    pub eol: Option<Eol>,
+    /// removes a utf-8 byte order mark at the start
+    pub strip_bom: bool,
+    /// removes spaces and tabs at the end of lines
+    pub trim_trailing_whitespace: bool,
+    /// files with these extensions are text, like for [`Render`]. when empty,
+    /// files which start with utf-8 without NUL bytes are text
+    pub extensions: Vec<String>,
+}
+
+impl TextFilter {
+    /// whether it changes anything, a filter which doesn't isn't used
+    pub fn is_set(&self) -> bool {
+        self.eol.is_some() || self.strip_bom || self.trim_trailing_whitespace
+    }
+}
+
+/// how names of destination files are changed, rules apply in order
+#[derive(Debug, PartialEq, Clone)]
+pub enum RenameRule {
+    /// the first match of `pattern` is replaced with `to`, which can use its
+    /// groups like "$1". names which don't match are kept
+    Regex {
+        pattern: String,
+        to: String,
+    },
+    Lower,
+    Upper,
+    /// name made of "{stem}", "{ext}", "{parent}", "{index}" and
+    /// "{mtime:<format>}", like "{stem}_{index}.{ext}"
+    Template(String),
+}
+
+/// per job options, these come from the json file. options given on command
+/// line are used for cmdline jobs, and as defaults for json jobs.
+#[derive(Debug, PartialEq, Clone)]
+pub struct JobOptions {
+    pub on_conflict: OnConflict,
+    pub exclude: Vec<String>,
+    pub preserve_mtime: bool,
+    pub structure: Structure,
+    pub verify: bool,
+    pub hash: HashAlgo,
+    /// destination files are written to a temp file, then renamed over the target
+    pub atomic: bool,
+    /// whole destination dir is built next to it, then swapped into place
+    pub staged: bool,
+    /// with `staged`, the replaced destination dir is kept as `<dst>.prev`
+    pub keep_previous: bool,
+    pub on_empty: OnEmpty,
+    /// shell command run before the job, "{dst}" is its destination
+    pub before: Option<String>,
+    /// shell command run after the job succeeded, "{dst}" is its destination
+    pub after: Option<String>,
+    /// shell command which writes each copied file, instead of copying it.
+    /// "{src}" is the source file, "{dst}" the file to write
+    pub exec: Option<String>,
+    /// copy only: "{name}" placeholders in contents of text files are
+    /// replaced with variables
+    pub render: Option<Render>,
+    /// copy only: contents of text files are normalized, after rendering
+    pub text: Option<TextFilter>,
+    /// renames files placed from dirs and filters
+    pub rename: Vec<RenameRule>,
+}
+
+impl Default for JobOptions {
+    fn default() -> Self {
+        Self {
+            on_conflict: OnConflict::default(),
+            exclude: Vec::new(),
+            preserve_mtime: false,
+            structure: Structure::default(),
+            verify: false,
+            hash: HashAlgo::default(),
+            atomic: true,
+            staged: false,
+            keep_previous: false,
+            on_empty: OnEmpty::default(),
+            before: None,
+            after: None,
+            exec: None,
+            render: None,
+            text: None,
+            rename: Vec::new(),
+        }
+    }
+}
+
+/// one operation of sources to a destination, what a cmdline run or a job of a
+/// json file describes. it's built like this:
+///
+/// ```no_run
+/// use esycpy::{Job, OnConflict};
+///
+/// let stats = Job::copy("assets/*.png", "out/")
+///     .exclude("*_draft.png")
+///     .on_conflict(OnConflict::Skip)
+///     .run()?;
+/// println!("{} files copied", stats.copied);
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Debug, PartialEq, Clone)]
+pub struct Job {
+    pub op: Operation,
+    /// files, dirs, or filters like "dir/*.ogg" and "dir/**.ogg" which also
+    /// match in sub dirs
+    pub from: Vec<PathBuf>,
+    /// when there are many sources, or it's a dir, files are placed inside it.
+    /// with many sources, dirs are placed inside it too, like "out/assets".
+    /// a trailing separator like "out/" marks a dir which doesn't exist yet
+    pub to: PathBuf,
+    pub opts: JobOptions,
+}
+
+impl Job {
+    pub fn new<S: Into<PathBuf>, D: Into<PathBuf>>(op: Operation, from: S, to: D) -> Self {
+        Self {
+            op,
+            from: vec![from.into()],
+            to: to.into(),
+            opts: JobOptions::default(),
+        }
+    }
+
+    pub fn copy<S: Into<PathBuf>, D: Into<PathBuf>>(from: S, to: D) -> Self {
+        Job::new(Operation::Copy_, from, to)
+    }
+
+    pub fn move_<S: Into<PathBuf>, D: Into<PathBuf>>(from: S, to: D) -> Self {
+        Job::new(Operation::Move, from, to)
+    }
+
+    pub fn hardlink<S: Into<PathBuf>, D: Into<PathBuf>>(from: S, to: D) -> Self {
+        Job::new(Operation::Hardlink, from, to)
+    }
+
+    /// adds another source
+    pub fn source<S: Into<PathBuf>>(mut self, from: S) -> Self {
+        self.from.push(from.into());
+        self
+    }
+
+    /// replaces all options
+    pub fn options(mut self, opts: JobOptions) -> Self {
+        self.opts = opts;
+        self
+    }
+
+    /// leaves out files and dirs whose name matches `pattern`, e.g. "*.tmp"
+    pub fn exclude<P: Into<String>>(mut self, pattern: P) -> Self {
+        self.opts.exclude.push(pattern.into());
+        self
+    }
+
+    pub fn on_conflict(mut self, on_conflict: OnConflict) -> Self {
+        self.opts.on_conflict = on_conflict;
+        self
+    }
+
+    pub fn preserve_mtime(mut self, preserve_mtime: bool) -> Self {
+        self.opts.preserve_mtime = preserve_mtime;
+        self
+    }
+
+    pub fn structure(mut self, structure: Structure) -> Self {
+        self.opts.structure = structure;
+        self
+    }
+
+    /// verifies transferred files with a checksum of `hash`
+    pub fn verify(mut self, hash: HashAlgo) -> Self {
+        self.opts.verify = true;
+        self.opts.hash = hash;
+        self
+    }
+
+    pub fn atomic(mut self, atomic: bool) -> Self {
+        self.opts.atomic = atomic;
+        self
+    }
+
+    pub fn staged(mut self, staged: bool) -> Self {
+        self.opts.staged = staged;
+        self
+    }
+
+    pub fn keep_previous(mut self, keep_previous: bool) -> Self {
+        self.opts.keep_previous = keep_previous;
+        self
+    }
+
+    pub fn on_empty(mut self, on_empty: OnEmpty) -> Self {
+        self.opts.on_empty = on_empty;
+        self
+    }
+
+    pub fn before<C: Into<String>>(mut self, command: C) -> Self {
+        self.opts.before = Some(command.into());
+        self
+    }
+
+    pub fn after<C: Into<String>>(mut self, command: C) -> Self {
+        self.opts.after = Some(command.into());
+        self
+    }
+
+    /// writes copied files with `command`, like "cwebp {src} -o {dst}"
+    pub fn exec<C: Into<String>>(mut self, command: C) -> Self {
+        self.opts.exec = Some(command.into());
+        self
+    }
+
+    /// puts variables in the contents of copied text files, see [`Render`]
+    pub fn render(mut self, render: Render) -> Self {
+        self.opts.render = Some(render);
+        self
+    }
+
+    /// normalizes copied text files, see [`TextFilter`]
+    pub fn text(mut self, text: TextFilter) -> Self {
+        self.opts.text = Some(text);
+        self
+    }
+
+    /// adds a rule which renames destination files, see [`RenameRule`]
+    pub fn rename(mut self, rule: RenameRule) -> Self {
+        self.opts.rename.push(rule);
+        self
+    }
+
+    /// source and destination of every file and dir the job would transfer,
+    /// nothing is changed
+    pub fn plan(&self) -> Result<Vec<Paths>> {
+        let mut paths = Vec::new();
+        for file_op in FileOp::from(self.clone()) {
+            paths.extend(file_op.plan()?);
+        }
+        Ok(paths)
+    }
+
+    /// source files and dirs the job reads, when one of them changes the job
+    /// has something new to do
+    pub fn inputs(&self) -> Result<Vec<PathBuf>> {
+        let mut inputs = Vec::new();
+        for file_op in FileOp::from(self.clone()) {
+            inputs.extend(file_op.inputs()?);
+        }
+        Ok(inputs)
+    }
+
+    /// runs the job on its own, without journal, progress or events.
+    /// use [`Run`] for those.
+    pub fn run(&self) -> Result<Stats> {
+        Run::new().job(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn job_built() {
+        let job = Job::copy("a", "b")
+            .source("c/*.txt")
+            .exclude("*.tmp")
+            .on_conflict(OnConflict::Skip)
+            .verify(HashAlgo::Sha256);
+        assert_eq!(
+            job,
+            Job {
+                op: Operation::Copy_,
+                from: vec![PathBuf::from("a"), PathBuf::from("c/*.txt")],
+                to: PathBuf::from("b"),
+                opts: JobOptions {
+                    exclude: vec![String::from("*.tmp")],
+                    on_conflict: OnConflict::Skip,
+                    verify: true,
+                    hash: HashAlgo::Sha256,
+                    ..Default::default()
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn job_planned_and_run() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let src = tmp_dir.path().join("src");
+        fs::create_dir_all(src.join("sub"))?;
+        fs::write(src.join("a.txt"), "a")?;
+        fs::write(src.join("sub").join("b.txt"), "b")?;
+        fs::write(src.join("c.tmp"), "c")?;
+        let dst = tmp_dir.path().join("dst");
+        let job = Job::copy(&src, &dst).exclude("*.tmp");
+        let mut planned: Vec<PathBuf> = job.plan()?.into_iter().map(|p| p.to).collect();
+        planned.sort();
+        assert_eq!(planned, [dst.join("a.txt"), dst.join("sub").join("b.txt")]);
+        assert!(!dst.exists());
+        assert_eq!(
+            job.inputs()?,
+            [
+                src.clone(),
+                src.join("a.txt"),
+                src.join("sub"),
+                src.join("sub").join("b.txt")
+            ]
+        );
+        let stats = job.run()?;
+        assert_eq!(stats.copied, 2);
+        assert_eq!(fs::read_to_string(dst.join("sub").join("b.txt"))?, "b");
+        assert!(!dst.join("c.tmp").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn job_rendered() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let src = tmp_dir.path().join("src");
+        fs::create_dir_all(&src)?;
+        fs::write(src.join("setup.txt"), "out={OutDir} {keep}")?;
+        fs::write(src.join("logo.png"), "{OutDir}")?;
+        let dst = tmp_dir.path().join("dst");
+        let render = Render {
+            variables: vec![("OutDir".to_owned(), "bin/Debug".to_owned())],
+            ..Default::default()
+        };
+        let stats = Job::copy(&src, &dst).render(render.clone()).run()?;
+        assert_eq!(stats.copied, 2);
+        assert_eq!(
+            fs::read_to_string(dst.join("setup.txt"))?,
+            "out=bin/Debug {keep}"
+        );
+        assert_eq!(fs::read_to_string(dst.join("logo.png"))?, "{OutDir}");
+        let strict = Render {
+            strict: true,
+            ..render
+        };
+        assert!(Job::copy(&src, &dst).render(strict).run().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn job_text_normalized() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let src = tmp_dir.path().join("src");
+        fs::create_dir_all(&src)?;
+        fs::write(src.join("a.glsl"), "\u{feff}void main() {} \r\n")?;
+        fs::write(src.join("b.bin"), b"\0 \r\n")?;
+        fs::write(src.join("setup.txt"), "out={OutDir}\r\n")?;
+        let dst = tmp_dir.path().join("dst");
+        let stats = Job::copy(&src, &dst)
+            .render(Render {
+                variables: vec![("OutDir".to_owned(), "bin".to_owned())],
+                ..Default::default()
+            })
+            .text(TextFilter {
+                eol: Some(Eol::Lf),
+                strip_bom: true,
+                trim_trailing_whitespace: true,
+                ..Default::default()
+            })
+            .run()?;
+        assert_eq!(stats.copied, 3);
+        assert_eq!(fs::read_to_string(dst.join("a.glsl"))?, "void main() {}\n");
+        assert_eq!(fs::read(dst.join("b.bin"))?, b"\0 \r\n");
+        assert_eq!(fs::read_to_string(dst.join("setup.txt"))?, "out=bin\n");
+        Ok(())
+    }
+
+    #[test]
+    fn job_renamed() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let src = tmp_dir.path().join("src");
+        fs::create_dir_all(src.join("Tex"))?;
+        fs::write(src.join("Tex").join("Rock_hi.PNG"), "rock")?;
+        fs::write(src.join("Grass_hi.png"), "grass")?;
+        let dst = tmp_dir.path().join("dst");
+        let job = Job::move_(&src, &dst)
+            .rename(RenameRule::Regex {
+                pattern: r"(?i)(.*)_hi\.png".to_owned(),
+                to: "$1.png".to_owned(),
+            })
+            .rename(RenameRule::Lower);
+        let stats = job.run()?;
+        assert_eq!(stats.moved, 2);
+        assert_eq!(
+            fs::read_to_string(dst.join("Tex").join("rock.png"))?,
+            "rock"
+        );
+        assert_eq!(fs::read_to_string(dst.join("grass.png"))?, "grass");
+
+        fs::write(src.join("a.png"), "a")?;
+        fs::write(src.join("b.png"), "b")?;
+        let same_name = Job::copy(src.join("*.png"), &dst)
+            .rename(RenameRule::Template("same.png".to_owned()))
+            .run();
+        assert!(same_name.is_err());
+        assert!(!dst.join("same.png").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn job_dst_templated() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let src = tmp_dir.path().join("dump");
+        fs::create_dir_all(&src)?;
+        fs::write(src.join("a.log"), "a")?;
+        fs::write(src.join("b.png"), "b")?;
+        let dst = tmp_dir.path().join("archive");
+        let job = Job::move_(src.join("*"), dst.join("{mtime:%Y}").join("{ext}"));
+        let mut planned: Vec<PathBuf> = job.plan()?.into_iter().map(|p| p.to).collect();
+        planned.sort();
+        let year = chrono::Local::now().format("%Y").to_string();
+        assert_eq!(
+            planned,
+            [
+                dst.join(&year).join("log").join("a.log"),
+                dst.join(&year).join("png").join("b.png"),
+            ]
+        );
+        assert_eq!(job.run()?.moved, 2);
+        assert!(dst.join(&year).join("png").join("b.png").is_file());
+        Ok(())
+    }
+
+    #[test]
+    fn job_dst_templated_single_file() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let src = tmp_dir.path().join("a.log");
+        fs::write(&src, "a")?;
+        let dst = tmp_dir.path().join("archive");
+        let job = Job::copy(&src, dst.join("{ext}"));
+        assert_eq!(job.run()?.copied, 1);
+        assert_eq!(fs::read_to_string(dst.join("log").join("a.log"))?, "a");
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn job_hooks_run() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let src = tmp_dir.path().join("src");
+        fs::create_dir_all(&src)?;
+        fs::write(src.join("a.txt"), "abc")?;
+        let dst = tmp_dir.path().join("dst");
+        let log = tmp_dir.path().join("log");
+        let stats = Job::copy(&src, &dst)
+            .before("mkdir -p {dst} && echo before > {dst}/../log")
+            .exec("tr a-z A-Z < {src} > {dst}")
+            .after("echo after >> \"$ESYCPY_DST/../log\"")
+            .run()?;
+        assert_eq!(stats.copied, 1);
+        assert_eq!(fs::read_to_string(dst.join("a.txt"))?, "ABC");
+        assert_eq!(fs::read_to_string(&log)?, "before\nafter\n");
+        assert!(Job::copy(&src, &dst).after("exit 1").run().is_err());
+        assert!(Job::move_(&src, &dst).exec("cp {src} {dst}").run().is_err());
+        Ok(())
+    }
+}
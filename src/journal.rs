@@ -0,0 +1,447 @@
+use anyhow::{bail, Result};
+use log::{info, trace, warn};
+use pathdiff::diff_paths;
+use serde::{Deserialize, Serialize};
+use std::cell::{Cell, RefCell};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use walkdir::WalkDir;
+
+/// journals of older runs are removed, so backups don't pile up
+const MAX_RUNS: usize = 10;
+const JOURNAL_FILE: &str = "journal.ndjson";
+const BACKUPS_DIR: &str = "backups";
+
+/// one change made by a run, and enough to revert it
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+enum Entry {
+    CreatedDir {
+        #[serde(with = "encoded_path")]
+        path: PathBuf,
+    },
+    Created {
+        #[serde(with = "encoded_path")]
+        path: PathBuf,
+    },
+    Moved {
+        #[serde(with = "encoded_path")]
+        from: PathBuf,
+        #[serde(with = "encoded_path")]
+        to: PathBuf,
+    },
+    Replaced {
+        #[serde(with = "encoded_path")]
+        path: PathBuf,
+        #[serde(with = "encoded_path")]
+        backup: PathBuf,
+    },
+}
+
+/// records every change of a run in `<journal dir>/<run id>/journal.ndjson`.
+/// files about to be replaced are kept in the "backups" dir next to it. nothing
+/// is written until the first change, so runs which change nothing leave no journal.
+#[derive(Debug)]
+pub struct Journal {
+    root: PathBuf,
+    id: String,
+    file: RefCell<Option<File>>,
+    backups: Cell<usize>,
+}
+
+impl Journal {
+    pub fn new(root: &Path) -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Self {
+            root: root.to_owned(),
+            id: format!("{}-{}", now.as_secs(), std::process::id()),
+            file: RefCell::new(None),
+            backups: Cell::new(0),
+        }
+    }
+
+    fn run_dir(&self) -> PathBuf {
+        self.root.join(&self.id)
+    }
+
+    fn open(&self) -> Result<()> {
+        if self.file.borrow().is_some() {
+            return Ok(());
+        }
+        prune_runs(&self.root)?;
+        fs::create_dir_all(self.run_dir().join(BACKUPS_DIR))?;
+        info!("journal of run {}: {:?}", self.id, self.run_dir());
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.run_dir().join(JOURNAL_FILE))?;
+        *self.file.borrow_mut() = Some(file);
+        Ok(())
+    }
+
+    fn record(&self, entry: Entry) -> Result<()> {
+        trace!("journal: {:?}", entry);
+        self.open()?;
+        let mut file = self.file.borrow_mut();
+        let file = file.as_mut().unwrap();
+        writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+        file.sync_data()?;
+        Ok(())
+    }
+
+    fn next_backup(&self) -> PathBuf {
+        let n = self.backups.get();
+        self.backups.set(n + 1);
+        self.run_dir().join(BACKUPS_DIR).join(n.to_string())
+    }
+
+    /// `path` didn't exist before this run
+    pub fn created(&self, path: &Path) -> Result<()> {
+        self.record(Entry::Created {
+            path: std::path::absolute(path)?,
+        })
+    }
+
+    /// creates `dir` with its parents, and records the dirs which didn't exist
+    pub fn create_dir_all(&self, dir: &Path) -> Result<()> {
+        let dir = std::path::absolute(dir)?;
+        let missing: Vec<&Path> = dir.ancestors().take_while(|d| !d.exists()).collect();
+        fs::create_dir_all(&dir)?;
+        for path in missing.into_iter().rev() {
+            self.record(Entry::CreatedDir {
+                path: path.to_owned(),
+            })?;
+        }
+        Ok(())
+    }
+
+    pub fn moved(&self, from: &Path, to: &Path) -> Result<()> {
+        self.record(Entry::Moved {
+            from: std::path::absolute(from)?,
+            to: std::path::absolute(to)?,
+        })
+    }
+
+    /// keeps a copy of a file which is about to be overwritten. a hardlink is
+    /// enough, as files are always replaced by a new one, never written in place.
+    /// a file on another file system than the journal isn't copied, so a run
+    /// doesn't copy every file it overwrites. its overwrite can't be undone.
+    pub fn backup(&self, path: &Path) -> Result<()> {
+        self.open()?;
+        let backup = self.next_backup();
+        if let Err(e) = fs::hard_link(path, &backup) {
+            warn!("{} isn't backed up in the journal: {}", path.display(), e);
+            return Ok(());
+        }
+        self.record(Entry::Replaced {
+            path: std::path::absolute(path)?,
+            backup,
+        })
+    }
+
+    /// moves a file or dir which is about to be removed into the backups
+    pub fn stash(&self, path: &Path) -> Result<()> {
+        self.open()?;
+        let backup = self.next_backup();
+        move_any(path, &backup)?;
+        self.record(Entry::Replaced {
+            path: std::path::absolute(path)?,
+            backup,
+        })
+    }
+
+    /// `path` was replaced by a new file or dir, the old one is at `old`
+    pub fn replaced(&self, path: &Path, old: &Path) -> Result<()> {
+        self.record(Entry::Replaced {
+            path: std::path::absolute(path)?,
+            backup: std::path::absolute(old)?,
+        })
+    }
+}
+
+/// reverts the changes of a run, the last run when `run` isn't given.
+/// returns the id of the reverted run.
+pub fn undo(root: &Path, run: Option<&str>) -> Result<String> {
+    let id = match run {
+        Some(id) => id.to_owned(),
+        None => match list_runs(root)?.pop() {
+            Some(id) => id,
+            None => bail!("no runs to undo in {}", root.display()),
+        },
+    };
+    let run_dir = root.join(&id);
+    let journal_file = run_dir.join(JOURNAL_FILE);
+    if !journal_file.is_file() {
+        bail!("no journal of run {} in {}", id, root.display());
+    }
+    let mut entries = Vec::new();
+    for line in BufReader::new(File::open(&journal_file)?).lines() {
+        let line = line?;
+        match serde_json::from_str::<Entry>(&line) {
+            Ok(entry) => entries.push(entry),
+            // a run which crashed can leave the last line half written
+            Err(e) => warn!("ignoring broken journal line {:?}: {}", line, e),
+        }
+    }
+    while let Some(entry) = entries.pop() {
+        trace!("undo: {:?}", entry);
+        if let Err(e) = revert(&entry) {
+            // what is left can be undone after fixing the error
+            entries.push(entry);
+            let mut file = File::create(&journal_file)?;
+            for entry in &entries {
+                writeln!(file, "{}", serde_json::to_string(entry)?)?;
+            }
+            return Err(e);
+        }
+    }
+    fs::remove_dir_all(&run_dir)?;
+    Ok(id)
+}
+
+fn revert(entry: &Entry) -> Result<()> {
+    match entry {
+        Entry::CreatedDir { path } => {
+            // leave dirs which got other files in the meantime
+            if let Err(e) = fs::remove_dir(path) {
+                warn!("can't remove dir {}: {}", path.display(), e);
+            }
+        }
+        Entry::Created { path } => {
+            if path.exists() {
+                remove_any(path)?;
+            } else {
+                warn!("created path is already gone: {}", path.display());
+            }
+        }
+        Entry::Moved { from, to } => {
+            if from.exists() {
+                bail!("can't move back to {}, it exists", from.display());
+            }
+            fs::create_dir_all(from.parent().unwrap())?;
+            move_any(to, from)?;
+        }
+        Entry::Replaced { path, backup } => {
+            if !backup.exists() {
+                bail!(
+                    "backup of {} is missing: {}",
+                    path.display(),
+                    backup.display()
+                );
+            }
+            if path.exists() {
+                remove_any(path)?;
+            }
+            move_any(backup, path)?;
+        }
+    }
+    Ok(())
+}
+
+/// run ids, oldest first
+fn list_runs(root: &Path) -> Result<Vec<String>> {
+    if !root.exists() {
+        return Ok(Vec::new());
+    }
+    let mut runs: Vec<String> = fs::read_dir(root)?
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().join(JOURNAL_FILE).is_file())
+        .filter_map(|e| e.file_name().into_string().ok())
+        .collect();
+    runs.sort_by_key(|id| run_key(id));
+    Ok(runs)
+}
+
+/// ids are "<unix secs>-<pid>"
+fn run_key(id: &str) -> (u64, u64) {
+    let mut parts = id.split('-').map(|p| p.parse().unwrap_or(0));
+    (parts.next().unwrap_or(0), parts.next().unwrap_or(0))
+}
+
+fn prune_runs(root: &Path) -> Result<()> {
+    let runs = list_runs(root)?;
+    if runs.len() >= MAX_RUNS {
+        for id in &runs[..=runs.len() - MAX_RUNS] {
+            trace!("remove journal of old run {}", id);
+            fs::remove_dir_all(root.join(id))?;
+        }
+    }
+    Ok(())
+}
+
+fn remove_any(path: &Path) -> io::Result<()> {
+    if path.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+/// whether a rename failed as `from` and `to` are on different devices.
+/// it's the raw error, `io::ErrorKind::CrossesDevices` needs rust 1.85
+pub(crate) fn crosses_devices(e: &io::Error) -> bool {
+    // EXDEV on unix, ERROR_NOT_SAME_DEVICE on windows
+    #[cfg(unix)]
+    const CROSSES_DEVICES: i32 = 18;
+    #[cfg(windows)]
+    const CROSSES_DEVICES: i32 = 17;
+    e.raw_os_error() == Some(CROSSES_DEVICES)
+}
+
+/// renames, or copies and removes when `from` and `to` are on different devices
+fn move_any(from: &Path, to: &Path) -> Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if crosses_devices(&e) => {
+            if from.is_dir() {
+                for entry in WalkDir::new(from) {
+                    let entry = entry?;
+                    let dst = to.join(diff_paths(entry.path(), from).unwrap());
+                    if entry.file_type().is_dir() {
+                        fs::create_dir_all(&dst)?;
+                    } else {
+                        fs::copy(entry.path(), &dst)?;
+                    }
+                }
+            } else {
+                fs::copy(from, to)?;
+            }
+            remove_any(from)?;
+            Ok(())
+        }
+        result => Ok(result?),
+    }
+}
+
+/// utf-8 paths are written as strings, other paths as their bytes
+pub mod encoded_path {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::ffi::OsString;
+    use std::path::{Path, PathBuf};
+
+    pub fn serialize<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+        match path.to_str() {
+            Some(path) => serializer.serialize_str(path),
+            None => serializer.serialize_bytes(path.as_os_str().as_encoded_bytes()),
+        }
+    }
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Encoded {
+        Str(String),
+        Bytes(Vec<u8>),
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
+        Ok(match Encoded::deserialize(deserializer)? {
+            Encoded::Str(path) => PathBuf::from(path),
+            // SAFETY: journals are only read on the platform which wrote them, so
+            // these are bytes from `as_encoded_bytes`
+            Encoded::Bytes(bytes) => {
+                PathBuf::from(unsafe { OsString::from_encoded_bytes_unchecked(bytes) })
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn entries_roundtrip() -> Result<()> {
+        let entry = Entry::Moved {
+            from: PathBuf::from("/a/b"),
+            to: PathBuf::from("/c"),
+        };
+        let line = serde_json::to_string(&entry)?;
+        assert_eq!(line, r#"{"action":"moved","from":"/a/b","to":"/c"}"#);
+        assert_eq!(serde_json::from_str::<Entry>(&line)?, entry);
+        #[cfg(unix)]
+        {
+            use std::ffi::OsStr;
+            use std::os::unix::ffi::OsStrExt;
+            let entry = Entry::Created {
+                path: PathBuf::from(OsStr::from_bytes(b"/a/\xff.txt")),
+            };
+            let line = serde_json::to_string(&entry)?;
+            assert_eq!(serde_json::from_str::<Entry>(&line)?, entry);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn undo_last_run() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let root = tmp_dir.path().join("journal");
+        let work = tmp_dir.path().join("work");
+        fs::create_dir_all(&work)?;
+        fs::write(work.join("old"), "old")?;
+        fs::write(work.join("moved"), "moved")?;
+
+        let journal = Journal::new(&root);
+        journal.create_dir_all(&work.join("new").join("sub"))?;
+        fs::write(work.join("new").join("sub").join("f"), "f")?;
+        journal.created(&work.join("new").join("sub").join("f"))?;
+        journal.backup(&work.join("old"))?;
+        fs::write(work.join("old.tmp"), "new")?;
+        fs::rename(work.join("old.tmp"), work.join("old"))?;
+        fs::rename(work.join("moved"), work.join("new").join("moved"))?;
+        journal.moved(&work.join("moved"), &work.join("new").join("moved"))?;
+
+        assert_eq!(list_runs(&root)?, vec![journal.id.clone()]);
+        assert_eq!(undo(&root, None)?, journal.id);
+        assert_eq!(fs::read_to_string(work.join("old"))?, "old");
+        assert_eq!(fs::read_to_string(work.join("moved"))?, "moved");
+        assert!(!work.join("new").exists());
+        assert!(list_runs(&root)?.is_empty());
+        assert!(undo(&root, None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn failed_undo_can_be_retried() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let root = tmp_dir.path().join("journal");
+        let work = tmp_dir.path().join("work");
+        fs::create_dir_all(&work)?;
+        fs::write(work.join("a"), "a")?;
+        fs::write(work.join("b"), "b")?;
+        let journal = Journal::new(&root);
+        fs::rename(work.join("a"), work.join("a2"))?;
+        journal.moved(&work.join("a"), &work.join("a2"))?;
+        fs::write(work.join("c"), "c")?;
+        journal.created(&work.join("c"))?;
+        // "a" is taken again, so it can't be moved back
+        fs::write(work.join("a"), "other")?;
+        assert!(undo(&root, Some(&journal.id)).is_err());
+        assert!(!work.join("c").exists());
+        fs::remove_file(work.join("a"))?;
+        undo(&root, Some(&journal.id))?;
+        assert_eq!(fs::read_to_string(work.join("a"))?, "a");
+        Ok(())
+    }
+
+    #[test]
+    fn old_runs_pruned() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let root = tmp_dir.path();
+        for i in 0..MAX_RUNS + 2 {
+            let dir = root.join(format!("{}-1", 1000 + i));
+            fs::create_dir_all(&dir)?;
+            fs::write(dir.join(JOURNAL_FILE), "")?;
+        }
+        let journal = Journal::new(root);
+        journal.created(&root.join("x"))?;
+        let runs = list_runs(root)?;
+        assert_eq!(runs.len(), MAX_RUNS);
+        assert_eq!(runs.last(), Some(&journal.id));
+        assert_eq!(runs[0], format!("{}-1", 1000 + 3));
+        Ok(())
+    }
+}
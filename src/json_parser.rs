@@ -1,91 +1,432 @@
-use crate::args::{ArgsType, Operation};
+use crate::job::{
+    Eol, HashAlgo, Job, JobOptions, OnConflict, OnEmpty, Operation, RenameRule, Render, Structure,
+};
+use anyhow::{anyhow, bail, Context, Result};
 use log::debug;
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
+use std::env;
 use std::fs;
+use std::iter;
 use std::path::{Path, PathBuf};
 
-pub fn get_json_args(args: ArgsType) -> Vec<ArgsType> {
-    match args {
-        ArgsType::Json {
-            json_file,
-            variables,
-        } => {
-            let json_def = parse_json(Path::new(&json_file));
-            map_variables(json_def, variables)
-        }
-        _ => unreachable!(),
-    }
+/// jobs of a json file and the files it includes, with `variables` put in their
+/// paths. `opts` are used for options which the json file doesn't set.
+///
+/// # Errors
+///
+/// when the json file can't be read or is invalid, or `variables` don't match
+/// the ones it uses
+pub fn load_jobs(
+    json_file: &Path,
+    variables: Option<Vec<(String, String)>>,
+    opts: &JobOptions,
+) -> Result<Vec<Job>> {
+    Ok(load_job_file(json_file, variables, opts)?.jobs)
 }
 
+/// jobs of a json file, and the files they were read from
+#[derive(Debug)]
+pub struct JobFile {
+    pub jobs: Vec<Job>,
+    /// the json file and all files it includes
+    pub files: Vec<PathBuf>,
+}
+
+/// same as [`load_jobs`], also listing the json files read
+pub fn load_job_file(
+    json_file: &Path,
+    variables: Option<Vec<(String, String)>>,
+    opts: &JobOptions,
+) -> Result<JobFile> {
+    let mut files = Vec::new();
+    let json_def = load_json(json_file, &variables, &mut Vec::new(), &mut files)?;
+    Ok(JobFile {
+        jobs: map_variables(json_def, variables, opts)?,
+        files,
+    })
+}
+
+/// `cmdline_opts` are options given on command line, used for what the json file doesn't set
 fn map_variables(
     asset_def: AssetRelocationDef,
     variables: Option<Vec<(String, String)>>,
-) -> Vec<ArgsType> {
-    assert_eq!(
-        variables.is_none(),
-        asset_def.variables_in_use.is_empty(),
-        "either json file is missing vars, or command line"
-    );
-    if variables.is_some() {
-        assert_eq!(
-            variables.clone().unwrap().len(),
-            asset_def.variables_in_use.len(),
-            "vars count mismatch"
-        );
+    cmdline_opts: &JobOptions,
+) -> Result<Vec<Job>> {
+    if variables.is_none() != asset_def.variables_in_use.is_empty() {
+        bail!("either json file is missing vars, or command line");
     }
-    let mut mapped_args: Vec<ArgsType> = Vec::new();
-    asset_def.jobs.into_iter().for_each(|mut d| {
-        let todo: Operation = match d.todo.as_str() {
-            "copy" => Operation::Copy_,
-            "move" => Operation::Move,
-            "hardlink" => Operation::Hardlink,
-            _ => panic!("unhandled operation"),
+    if let Some(variables) = &variables {
+        if variables.len() != asset_def.variables_in_use.len() {
+            bail!(
+                "vars count mismatch: {} given, json file uses {}",
+                variables.len(),
+                asset_def.variables_in_use.len()
+            );
+        }
+    }
+    let mut mapped_args: Vec<Job> = Vec::new();
+    for d in asset_def.jobs {
+        let todo: Operation = match d.options.todo.as_deref() {
+            Some("copy") => Operation::Copy_,
+            Some("move") => Operation::Move,
+            Some("hardlink") => Operation::Hardlink,
+            Some(todo) => bail!("unhandled operation \"{}\"", todo),
+            None => bail!("job has no operation, set \"todo\" in job or defaults"),
         };
-        if let Some(variables) = variables.clone() {
-            variables.iter().for_each(|v| {
-                d.src = d
-                    .src
-                    .replace(&format!("{{{}}}", v.0.as_str()), v.1.as_str());
-                d.dst = d
-                    .dst
-                    .replace(&format!("{{{}}}", v.0.as_str()), v.1.as_str());
-            });
+        let resolve = |path: &str| -> Result<PathBuf> {
+            Ok(d.base_dir.join(resolve_variables(path, &variables)?))
         };
-        let mapped_arg = ArgsType::CmdLine {
+        let mut opts = d.options.to_job_options(cmdline_opts)?;
+        let replace = |command: Option<String>| command.map(|c| replace_variables(&c, &variables));
+        opts.before = replace(opts.before);
+        opts.after = replace(opts.after);
+        opts.exec = replace(opts.exec);
+        if opts.exec.is_some() && todo != Operation::Copy_ {
+            bail!("\"exec\" only works with copy");
+        }
+        if let Some(render) = &mut opts.render {
+            if todo != Operation::Copy_ || opts.exec.is_some() {
+                bail!("\"render\" only works with copy, without \"exec\"");
+            }
+            // contents get the same variables as paths
+            render.variables = variables.clone().unwrap_or_default();
+        }
+        if opts.text.is_some() && (todo != Operation::Copy_ || opts.exec.is_some()) {
+            bail!("\"eol\", \"strip_bom\" and \"trim_trailing_whitespace\" only work with copy, without \"exec\"");
+        }
+        let mapped_arg = Job {
             op: todo,
-            from: PathBuf::from(d.src),
-            to: PathBuf::from(d.dst),
+            from: d
+                .src
+                .iter()
+                .map(|src| resolve(src))
+                .collect::<Result<_>>()?,
+            to: resolve(&d.dst)?,
+            opts,
         };
         mapped_args.push(mapped_arg)
-    });
-    mapped_args
+    }
+    Ok(mapped_args)
+}
+
+/// puts values of "{var_name}" variables in `text`, and the home dir for a leading "~"
+pub fn resolve_variables(text: &str, variables: &Option<Vec<(String, String)>>) -> Result<String> {
+    expand_home(&replace_variables(text, variables))
+}
+
+fn replace_variables(text: &str, variables: &Option<Vec<(String, String)>>) -> String {
+    let mut text = text.to_owned();
+    if let Some(variables) = variables {
+        variables.iter().for_each(|v| {
+            text = text.replace(&format!("{{{}}}", v.0.as_str()), v.1.as_str());
+        });
+    }
+    text
+}
+
+/// replaces a leading "~" with the home dir of the user
+fn expand_home(path: &str) -> Result<String> {
+    if path == "~" || path.starts_with("~/") || path.starts_with("~\\") {
+        let home = env::var("HOME")
+            .or_else(|_| env::var("USERPROFILE"))
+            .map_err(|_| anyhow!("home dir isn't known, set HOME"))?;
+        Ok(format!("{}{}", home, &path[1..]))
+    } else {
+        Ok(path.to_owned())
+    }
 }
 
 #[derive(Deserialize)]
 struct AssetRelocationDef {
     variables_in_use: Vec<String>,
+    #[serde(default)]
+    include: Vec<String>,
+    #[serde(default)]
+    defaults: OptionConfigs,
+    /// dir against which relative job paths are resolved. it's the json file's
+    /// dir when not given, "cwd" is current working dir, or else a path
+    /// relative to the json file
+    #[serde(default)]
+    base_dir: Option<String>,
     jobs: Vec<JobConfigs>,
 }
 
 #[derive(Deserialize, Debug)]
 struct JobConfigs {
-    todo: String,
-    src: String,
+    #[serde(deserialize_with = "one_or_many")]
+    src: Vec<String>,
     dst: String,
+    #[serde(flatten)]
+    options: OptionConfigs,
+    #[serde(skip)]
+    base_dir: PathBuf,
+}
+
+/// a single value, or an array of values
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+impl<T> OneOrMany<T> {
+    fn into_vec(self) -> Vec<T> {
+        match self {
+            OneOrMany::One(value) => vec![value],
+            OneOrMany::Many(values) => values,
+        }
+    }
 }
 
-fn parse_json(path: &Path) -> AssetRelocationDef {
-    let json_text = fs::read_to_string(path).expect("couldn't read file");
-    debug!("{} file is read", path.to_str().unwrap());
-    let json_data: AssetRelocationDef =
-        serde_json::from_str(&json_text).expect("json file format doesn't comply");
+/// "src" can be a single path or an array of paths
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(OneOrMany::deserialize(deserializer)?.into_vec())
+}
+
+/// a rename rule is "lower", "upper", a template like "{stem}_{index}.{ext}",
+/// or a regex like { "match": "(.*)_hi\\.png", "to": "$1.png" }
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+enum RenameConfig {
+    Name(String),
+    Regex {
+        #[serde(rename = "match")]
+        pattern: String,
+        to: String,
+    },
+}
+
+impl RenameConfig {
+    fn to_rule(&self) -> RenameRule {
+        match self {
+            RenameConfig::Name(name) if name == "lower" => RenameRule::Lower,
+            RenameConfig::Name(name) if name == "upper" => RenameRule::Upper,
+            RenameConfig::Name(template) => RenameRule::Template(template.clone()),
+            RenameConfig::Regex { pattern, to } => RenameRule::Regex {
+                pattern: pattern.clone(),
+                to: to.clone(),
+            },
+        }
+    }
+}
+
+/// options of a job. all are optional, anything a job leaves unset
+/// is taken from the "defaults" block of its json file.
+#[derive(Deserialize, Debug, Clone, Default)]
+struct OptionConfigs {
+    todo: Option<String>,
+    on_conflict: Option<String>,
+    exclude: Option<Vec<String>>,
+    preserve_mtime: Option<bool>,
+    structure: Option<String>,
+    verify: Option<bool>,
+    hash: Option<String>,
+    atomic: Option<bool>,
+    staged: Option<bool>,
+    keep_previous: Option<bool>,
+    on_empty: Option<String>,
+    before: Option<String>,
+    after: Option<String>,
+    exec: Option<String>,
+    render: Option<bool>,
+    render_extensions: Option<Vec<String>>,
+    render_strict: Option<bool>,
+    eol: Option<String>,
+    strip_bom: Option<bool>,
+    trim_trailing_whitespace: Option<bool>,
+    text_extensions: Option<Vec<String>>,
+    rename: Option<OneOrMany<RenameConfig>>,
+}
+
+impl OptionConfigs {
+    fn inherit(&mut self, defaults: &OptionConfigs) {
+        let defaults = defaults.clone();
+        self.todo = self.todo.take().or(defaults.todo);
+        self.on_conflict = self.on_conflict.take().or(defaults.on_conflict);
+        self.exclude = self.exclude.take().or(defaults.exclude);
+        self.preserve_mtime = self.preserve_mtime.or(defaults.preserve_mtime);
+        self.structure = self.structure.take().or(defaults.structure);
+        self.verify = self.verify.or(defaults.verify);
+        self.hash = self.hash.take().or(defaults.hash);
+        self.atomic = self.atomic.or(defaults.atomic);
+        self.staged = self.staged.or(defaults.staged);
+        self.keep_previous = self.keep_previous.or(defaults.keep_previous);
+        self.on_empty = self.on_empty.take().or(defaults.on_empty);
+        self.before = self.before.take().or(defaults.before);
+        self.after = self.after.take().or(defaults.after);
+        self.exec = self.exec.take().or(defaults.exec);
+        self.render = self.render.or(defaults.render);
+        self.render_extensions = self.render_extensions.take().or(defaults.render_extensions);
+        self.render_strict = self.render_strict.or(defaults.render_strict);
+        self.eol = self.eol.take().or(defaults.eol);
+        self.strip_bom = self.strip_bom.or(defaults.strip_bom);
+        self.trim_trailing_whitespace = self
+            .trim_trailing_whitespace
+            .or(defaults.trim_trailing_whitespace);
+        self.text_extensions = self.text_extensions.take().or(defaults.text_extensions);
+        self.rename = self.rename.take().or(defaults.rename);
+    }
+
+    fn to_job_options(&self, base: &JobOptions) -> Result<JobOptions> {
+        let on_conflict = match self.on_conflict.as_deref() {
+            None => base.on_conflict,
+            Some("overwrite") => OnConflict::Overwrite,
+            Some("skip") => OnConflict::Skip,
+            Some("error") => OnConflict::Error,
+            Some(value) => bail!("unhandled on_conflict value \"{}\"", value),
+        };
+        let structure = match self.structure.as_deref() {
+            None => base.structure,
+            Some("auto") => Structure::Auto,
+            Some("keep") => Structure::Keep,
+            Some("flatten") => Structure::Flatten,
+            Some(value) => bail!("unhandled structure value \"{}\"", value),
+        };
+        let hash = match self.hash.as_deref() {
+            None => base.hash,
+            Some(name) => HashAlgo::from_name(name)
+                .ok_or_else(|| anyhow!("unhandled hash value \"{}\"", name))?,
+        };
+        let on_empty = match self.on_empty.as_deref() {
+            None => base.on_empty,
+            Some(name) => OnEmpty::from_name(name)
+                .ok_or_else(|| anyhow!("unhandled on_empty value \"{}\"", name))?,
+        };
+        let render = match self.render {
+            None => base.render.clone(),
+            Some(false) => None,
+            Some(true) => Some(Render::default()),
+        }
+        .map(|mut render| {
+            if let Some(extensions) = &self.render_extensions {
+                render.extensions = extensions.clone();
+            }
+            render.strict = self.render_strict.unwrap_or(render.strict);
+            render
+        });
+        let mut text = base.text.clone().unwrap_or_default();
+        if let Some(name) = self.eol.as_deref() {
+            text.eol = Some(
+                Eol::from_name(name).ok_or_else(|| anyhow!("unhandled eol value \"{}\"", name))?,
+            );
+        }
+        text.strip_bom = self.strip_bom.unwrap_or(text.strip_bom);
+        text.trim_trailing_whitespace = self
+            .trim_trailing_whitespace
+            .unwrap_or(text.trim_trailing_whitespace);
+        if let Some(extensions) = &self.text_extensions {
+            text.extensions = extensions.clone();
+        }
+        Ok(JobOptions {
+            on_conflict,
+            exclude: self.exclude.clone().unwrap_or_else(|| base.exclude.clone()),
+            preserve_mtime: self.preserve_mtime.unwrap_or(base.preserve_mtime),
+            structure,
+            verify: self.verify.unwrap_or(base.verify),
+            hash,
+            atomic: self.atomic.unwrap_or(base.atomic),
+            staged: self.staged.unwrap_or(base.staged),
+            keep_previous: self.keep_previous.unwrap_or(base.keep_previous),
+            on_empty,
+            before: self.before.clone().or_else(|| base.before.clone()),
+            after: self.after.clone().or_else(|| base.after.clone()),
+            exec: self.exec.clone().or_else(|| base.exec.clone()),
+            render,
+            text: Some(text).filter(|t| t.is_set()),
+            rename: match &self.rename {
+                None => base.rename.clone(),
+                Some(rename) => rename
+                    .clone()
+                    .into_vec()
+                    .iter()
+                    .map(RenameConfig::to_rule)
+                    .collect(),
+            },
+        })
+    }
+}
+
+/// reads the json file and merges all files it includes, recursively.
+/// included jobs come before the jobs of the including file, in include order.
+/// `chain` holds the files currently being loaded and is used to report include cycles.
+/// every loaded file is added to `files`.
+fn load_json(
+    path: &Path,
+    variables: &Option<Vec<(String, String)>>,
+    chain: &mut Vec<PathBuf>,
+    files: &mut Vec<PathBuf>,
+) -> Result<AssetRelocationDef> {
+    let path = path
+        .canonicalize()
+        .with_context(|| format!("json file not found: {}", path.display()))?;
+    if let Some(pos) = chain.iter().position(|p| p == &path) {
+        let cycle: Vec<String> = chain[pos..]
+            .iter()
+            .chain(iter::once(&path))
+            .map(|p| p.display().to_string())
+            .collect();
+        bail!("include cycle detected: {}", cycle.join(" -> "));
+    }
+    chain.push(path.clone());
+    files.push(path.clone());
+    let mut json_def = parse_json(&path)?;
+    let base_dir = path.parent().unwrap().to_owned();
+    let jobs_base_dir = match json_def
+        .base_dir
+        .as_ref()
+        .map(|d| replace_variables(d, variables))
+    {
+        None => base_dir.clone(),
+        Some(dir) if dir == "cwd" => env::current_dir().context("current dir isn't accessible")?,
+        Some(dir) => base_dir.join(expand_home(&dir)?),
+    };
+    json_def
+        .jobs
+        .iter_mut()
+        .for_each(|j| j.base_dir = jobs_base_dir.clone());
+    let mut variables_in_use: Vec<String> = Vec::new();
+    let mut jobs: Vec<JobConfigs> = Vec::new();
+    for include in json_def.include.drain(..) {
+        let include_path = base_dir.join(expand_home(&replace_variables(&include, variables))?);
+        let included = load_json(&include_path, variables, chain, files)?;
+        variables_in_use.extend(included.variables_in_use);
+        jobs.extend(included.jobs);
+    }
+    variables_in_use.append(&mut json_def.variables_in_use);
+    jobs.append(&mut json_def.jobs);
+    jobs.iter_mut()
+        .for_each(|j| j.options.inherit(&json_def.defaults));
+    let mut unique_vars: Vec<String> = Vec::new();
+    variables_in_use.into_iter().for_each(|v| {
+        if !unique_vars.contains(&v) {
+            unique_vars.push(v);
+        }
+    });
+    json_def.variables_in_use = unique_vars;
+    json_def.jobs = jobs;
+    chain.pop();
+    Ok(json_def)
+}
+
+fn parse_json(path: &Path) -> Result<AssetRelocationDef> {
+    let json_text = fs::read_to_string(path)
+        .with_context(|| format!("couldn't read file {}", path.display()))?;
+    debug!("{} file is read", path.display());
+    let json_data: AssetRelocationDef = serde_json::from_str(&json_text)
+        .with_context(|| format!("json file format doesn't comply: {}", path.display()))?;
     debug!("json file is parsed");
-    json_data
+    Ok(json_data)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::job::TextFilter;
+    use tempfile::TempDir;
     #[test]
     fn map_var_correct() {
         let asset_def = AssetRelocationDef {
@@ -95,21 +436,36 @@ mod tests {
                 "var3".to_owned(),
                 "var4".to_owned(),
             ],
+            include: vec![],
+            defaults: OptionConfigs::default(),
+            base_dir: None,
             jobs: vec![
                 JobConfigs {
-                    todo: "copy".to_owned(),
-                    src: "this/is/{var1}/yes".to_owned(),
+                    src: vec!["this/is/{var1}/yes".to_owned()],
                     dst: "this/is/{var2}/yes".to_owned(),
+                    options: OptionConfigs {
+                        todo: Some("copy".to_owned()),
+                        ..Default::default()
+                    },
+                    base_dir: PathBuf::new(),
                 },
                 JobConfigs {
-                    todo: "hardlink".to_owned(),
-                    src: "this/is/{var4}/yes".to_owned(),
+                    src: vec!["this/is/{var4}/yes".to_owned()],
                     dst: "this/is/{var3}/yes".to_owned(),
+                    options: OptionConfigs {
+                        todo: Some("hardlink".to_owned()),
+                        ..Default::default()
+                    },
+                    base_dir: PathBuf::new(),
                 },
                 JobConfigs {
-                    todo: "move".to_owned(),
-                    src: "this/is/{var2}/yes".to_owned(),
+                    src: vec!["this/is/{var2}/yes".to_owned()],
                     dst: "this/is/{var3}/yes".to_owned(),
+                    options: OptionConfigs {
+                        todo: Some("move".to_owned()),
+                        ..Default::default()
+                    },
+                    base_dir: PathBuf::new(),
                 },
             ],
         };
@@ -120,9 +476,9 @@ mod tests {
             (String::from("var4"), String::from("VAR4")),
         ]);
         let mut ops = vec![Operation::Move, Operation::Hardlink, Operation::Copy_];
-        let mut arg_types: Vec<ArgsType> = Vec::new();
+        let mut arg_types: Vec<Job> = Vec::new();
         asset_def.jobs.iter().for_each(|d| {
-            arg_types.push(ArgsType::CmdLine {
+            arg_types.push(Job {
                 op: ops.pop().unwrap(),
                 to: PathBuf::from(
                     d.dst
@@ -130,108 +486,128 @@ mod tests {
                         .replace("{", "")
                         .replace("}", ""),
                 ),
-                from: PathBuf::from(
-                    d.src
+                from: vec![PathBuf::from(
+                    d.src[0]
                         .replace("var", "VAR")
                         .replace("{", "")
                         .replace("}", ""),
-                ),
+                )],
+                opts: JobOptions::default(),
             })
         });
-        assert_eq!(arg_types, map_variables(asset_def, variables));
+        assert_eq!(
+            arg_types,
+            map_variables(asset_def, variables, &JobOptions::default()).unwrap()
+        );
     }
 
     #[test]
     fn no_vars() {
         let asset_def = AssetRelocationDef {
             variables_in_use: vec![],
+            include: vec![],
+            defaults: OptionConfigs::default(),
+            base_dir: None,
             jobs: vec![
                 JobConfigs {
-                    todo: "copy".to_owned(),
-                    src: "this/is/var1/yes".to_owned(),
+                    src: vec!["this/is/var1/yes".to_owned()],
                     dst: "this/is/var2/yes".to_owned(),
+                    options: OptionConfigs {
+                        todo: Some("copy".to_owned()),
+                        ..Default::default()
+                    },
+                    base_dir: PathBuf::new(),
                 },
                 JobConfigs {
-                    todo: "hardlink".to_owned(),
-                    src: "this/is/var4/yes".to_owned(),
+                    src: vec!["this/is/var4/yes".to_owned()],
                     dst: "this/is/var3/yes".to_owned(),
+                    options: OptionConfigs {
+                        todo: Some("hardlink".to_owned()),
+                        ..Default::default()
+                    },
+                    base_dir: PathBuf::new(),
                 },
                 JobConfigs {
-                    todo: "move".to_owned(),
-                    src: "this/is/var2/yes".to_owned(),
+                    src: vec!["this/is/var2/yes".to_owned()],
                     dst: "this/is/var3/yes".to_owned(),
+                    options: OptionConfigs {
+                        todo: Some("move".to_owned()),
+                        ..Default::default()
+                    },
+                    base_dir: PathBuf::new(),
                 },
             ],
         };
         let variables = None;
         let mut ops = vec![Operation::Move, Operation::Hardlink, Operation::Copy_];
-        let mut arg_types: Vec<ArgsType> = Vec::new();
+        let mut arg_types: Vec<Job> = Vec::new();
         asset_def.jobs.iter().for_each(|d| {
-            arg_types.push(ArgsType::CmdLine {
+            arg_types.push(Job {
                 op: ops.pop().unwrap(),
                 to: PathBuf::from(d.dst.clone()),
-                from: PathBuf::from(d.src.clone()),
+                from: vec![PathBuf::from(d.src[0].clone())],
+                opts: JobOptions::default(),
             })
         });
-        assert_eq!(arg_types, map_variables(asset_def, variables));
+        assert_eq!(
+            arg_types,
+            map_variables(asset_def, variables, &JobOptions::default()).unwrap()
+        );
     }
 
     #[test]
-    #[should_panic(expected = "either json file is missing vars, or command line")]
     fn incompatible_vars() {
         let asset_def = AssetRelocationDef {
             variables_in_use: vec![],
+            include: vec![],
+            defaults: OptionConfigs::default(),
+            base_dir: None,
             jobs: vec![JobConfigs {
-                todo: "copy".to_owned(),
-                src: "this/is/var1/yes".to_owned(),
+                src: vec!["this/is/var1/yes".to_owned()],
                 dst: "this/is/var2/yes".to_owned(),
+                options: OptionConfigs {
+                    todo: Some("copy".to_owned()),
+                    ..Default::default()
+                },
+                base_dir: PathBuf::new(),
             }],
         };
         let variables = Some(vec![
             (String::from("var1"), String::from("VAR1")),
             (String::from("var3"), String::from("VAR3")),
         ]);
-        let mut ops = vec![Operation::Copy_];
-        let mut arg_types: Vec<ArgsType> = Vec::new();
-        asset_def.jobs.iter().for_each(|d| {
-            arg_types.push(ArgsType::CmdLine {
-                op: ops.pop().unwrap(),
-                to: PathBuf::from(d.dst.clone()),
-                from: PathBuf::from(d.src.clone()),
-            })
-        });
-        assert_eq!(arg_types, map_variables(asset_def, variables));
+        let err = map_variables(asset_def, variables, &JobOptions::default()).unwrap_err();
+        assert!(format!("{:#}", err).contains("either json file is missing vars, or command line"));
     }
 
     #[test]
-    #[should_panic(expected = "vars count mismatch")]
     fn vars_count_mismatch() {
         let asset_def = AssetRelocationDef {
             variables_in_use: vec!["var1".to_owned(), "var2".to_owned()],
+            include: vec![],
+            defaults: OptionConfigs::default(),
+            base_dir: None,
             jobs: vec![JobConfigs {
-                todo: "move".to_owned(),
-                src: "this/is/var2/yes".to_owned(),
+                src: vec!["this/is/var2/yes".to_owned()],
                 dst: "this/is/var3/yes".to_owned(),
+                options: OptionConfigs {
+                    todo: Some("move".to_owned()),
+                    ..Default::default()
+                },
+                base_dir: PathBuf::new(),
             }],
         };
         let variables = Some(vec![(String::from("var1"), String::from("VAR1"))]);
-        let mut ops = vec![Operation::Move];
-        let mut arg_types: Vec<ArgsType> = Vec::new();
-        asset_def.jobs.iter().for_each(|d| {
-            arg_types.push(ArgsType::CmdLine {
-                op: ops.pop().unwrap(),
-                to: PathBuf::from(d.dst.replace("var", "VAR")),
-                from: PathBuf::from(d.src.replace("var", "VAR")),
-            })
-        });
-        assert_eq!(arg_types, map_variables(asset_def, variables));
+        let err = map_variables(asset_def, variables, &JobOptions::default()).unwrap_err();
+        assert!(format!("{:#}", err).contains("vars count mismatch"));
     }
 
     #[test]
-    fn get_json_args_pass() {
-        let input_json_args = ArgsType::Json {
-            json_file: Path::new("./test_files/asset_relocation_def.json").to_owned(),
-            variables: Some(vec![
+    fn load_jobs_pass() {
+        let base = Path::new("./test_files").canonicalize().unwrap();
+        let json_jobs = load_jobs(
+            Path::new("./test_files/asset_relocation_def.json"),
+            Some(vec![
                 (String::from("Configuration"), String::from("debug")),
                 (String::from("ProjectName"), String::from("test_proj")),
                 (
@@ -247,19 +623,358 @@ mod tests {
                     String::from("c:/Users/test/out_dir"),
                 ),
             ]),
-        };
+            &JobOptions::default(),
+        )
+        .unwrap();
+        let out_args = vec![
+            Job {
+                op: Operation::Hardlink,
+                from: vec![base.join("c:/Users/test/sol_dir/../Bink2/lib/*.dll")],
+                to: base.join("c:/Users/test/out_dir"),
+                opts: JobOptions::default(),
+            },
+            Job {
+                op: Operation::Move,
+                from: vec![base.join("c:/Users/test/proj_dir/assets")],
+                to: base.join("c:/Users/test/out_dir/debug/Games/test_proj"),
+                opts: JobOptions::default(),
+            },
+        ];
+        assert_eq!(out_args, json_jobs);
+    }
+
+    #[test]
+    fn includes_merged() {
+        let base = Path::new("./test_files/includes").canonicalize().unwrap();
+        let out_dir = env::temp_dir().join("out_dir");
+        let json_jobs = load_jobs(
+            Path::new("./test_files/includes/main.json"),
+            Some(vec![
+                (String::from("OutDir"), out_dir.to_str().unwrap().to_owned()),
+                (String::from("ExtraDir"), String::from("extra")),
+            ]),
+            &JobOptions::default(),
+        )
+        .unwrap();
         let out_args = vec![
-            ArgsType::CmdLine {
+            Job {
                 op: Operation::Hardlink,
-                from: PathBuf::from("c:/Users/test/sol_dir/../Bink2/lib/*.dll"),
-                to: PathBuf::from("c:/Users/test/out_dir"),
+                from: vec![base.join("shared/*.dll")],
+                to: out_dir.clone(),
+                opts: JobOptions::default(),
+            },
+            Job {
+                op: Operation::Copy_,
+                from: vec![base.join("extra").join("setup.txt")],
+                to: out_dir.join("setup.txt"),
+                opts: JobOptions::default(),
             },
-            ArgsType::CmdLine {
+            Job {
                 op: Operation::Move,
-                from: PathBuf::from("c:/Users/test/proj_dir/assets"),
-                to: PathBuf::from("c:/Users/test/out_dir/debug/Games/test_proj"),
+                from: vec![base.join("game/assets")],
+                to: out_dir.join("game"),
+                opts: JobOptions::default(),
+            },
+        ];
+        assert_eq!(out_args, json_jobs);
+        let job_file = load_job_file(
+            Path::new("./test_files/includes/main.json"),
+            Some(vec![
+                (String::from("OutDir"), out_dir.to_str().unwrap().to_owned()),
+                (String::from("ExtraDir"), String::from("extra")),
+            ]),
+            &JobOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(job_file.jobs, out_args);
+        assert_eq!(
+            job_file.files,
+            [
+                base.join("main.json"),
+                base.join("common_assets.json"),
+                base.join("extra").join("extra_jobs.json"),
+            ]
+        );
+    }
+
+    #[test]
+    fn include_cycle() {
+        let err = load_jobs(
+            Path::new("./test_files/includes/cycle_a.json"),
+            None,
+            &JobOptions::default(),
+        )
+        .unwrap_err();
+        assert!(format!("{:#}", err).contains("include cycle detected"));
+    }
+
+    #[test]
+    fn invalid_json_file() {
+        let tmp_dir = TempDir::new().unwrap();
+        let json_file = tmp_dir.path().join("jobs.json");
+        let load = || load_jobs(&json_file, None, &JobOptions::default()).unwrap_err();
+        assert!(format!("{:#}", load()).contains("json file not found"));
+        fs::write(&json_file, r#"{ "jobs": [ { "src": "a" "#).unwrap();
+        assert!(format!("{:#}", load()).contains("json file format doesn't comply"));
+        fs::write(
+            &json_file,
+            r#"{ "variables_in_use": [], "jobs": [ { "src": "a", "dst": "b", "todo": "zip" } ] }"#,
+        )
+        .unwrap();
+        assert_eq!(load().to_string(), "unhandled operation \"zip\"");
+    }
+
+    #[test]
+    fn defaults_inherited() {
+        let base = Path::new("./test_files").canonicalize().unwrap();
+        let json_jobs = load_jobs(
+            Path::new("./test_files/job_defaults.json"),
+            None,
+            &JobOptions::default(),
+        )
+        .unwrap();
+        let out_args = vec![
+            Job {
+                op: Operation::Copy_,
+                from: vec![base.join("assets/*.xml")],
+                to: base.join("out"),
+                opts: JobOptions {
+                    on_conflict: OnConflict::Skip,
+                    exclude: vec!["*.tmp".to_owned(), ".git".to_owned()],
+                    preserve_mtime: true,
+                    structure: Structure::Auto,
+                    verify: true,
+                    hash: HashAlgo::Sha256,
+                    atomic: true,
+                    staged: false,
+                    keep_previous: false,
+                    on_empty: OnEmpty::Error,
+                    before: None,
+                    after: Some("touch {dst}/.done".to_owned()),
+                    exec: None,
+                    render: None,
+                    text: Some(TextFilter {
+                        eol: Some(Eol::Lf),
+                        strip_bom: true,
+                        ..Default::default()
+                    }),
+                    rename: vec![
+                        RenameRule::Regex {
+                            pattern: r"(.*)_hi\.xml".to_owned(),
+                            to: "$1.xml".to_owned(),
+                        },
+                        RenameRule::Lower,
+                    ],
+                },
+            },
+            Job {
+                op: Operation::Hardlink,
+                from: vec![base.join("assets/content")],
+                to: base.join("out/content"),
+                opts: JobOptions {
+                    on_conflict: OnConflict::Overwrite,
+                    exclude: vec![],
+                    preserve_mtime: true,
+                    structure: Structure::Flatten,
+                    verify: false,
+                    hash: HashAlgo::Sha256,
+                    atomic: false,
+                    staged: true,
+                    keep_previous: true,
+                    on_empty: OnEmpty::Ok,
+                    before: Some("echo start".to_owned()),
+                    after: Some("touch {dst}/.done".to_owned()),
+                    exec: None,
+                    render: None,
+                    text: None,
+                    rename: vec![],
+                },
             },
         ];
-        assert_eq!(out_args, get_json_args(input_json_args));
+        assert_eq!(out_args, json_jobs);
+    }
+
+    #[test]
+    fn exec_needs_copy() {
+        let asset_def = AssetRelocationDef {
+            variables_in_use: vec![],
+            include: vec![],
+            defaults: OptionConfigs::default(),
+            base_dir: None,
+            jobs: vec![JobConfigs {
+                src: vec!["this/is/var1/yes".to_owned()],
+                dst: "this/is/var2/yes".to_owned(),
+                options: OptionConfigs {
+                    todo: Some("move".to_owned()),
+                    exec: Some("gzip -c {src} > {dst}".to_owned()),
+                    ..Default::default()
+                },
+                base_dir: PathBuf::new(),
+            }],
+        };
+        let err = map_variables(asset_def, None, &JobOptions::default()).unwrap_err();
+        assert!(format!("{:#}", err).contains("\"exec\" only works with copy"));
+    }
+
+    #[test]
+    fn render_gets_variables() {
+        let asset_def = AssetRelocationDef {
+            variables_in_use: vec!["{OutDir}".to_owned()],
+            include: vec![],
+            defaults: OptionConfigs::default(),
+            base_dir: None,
+            jobs: vec![JobConfigs {
+                src: vec!["assets/setup.txt".to_owned()],
+                dst: "{OutDir}/setup.txt".to_owned(),
+                options: OptionConfigs {
+                    todo: Some("copy".to_owned()),
+                    render: Some(true),
+                    render_strict: Some(true),
+                    render_extensions: Some(vec![".txt".to_owned()]),
+                    ..Default::default()
+                },
+                base_dir: PathBuf::new(),
+            }],
+        };
+        let variables = vec![("OutDir".to_owned(), "bin".to_owned())];
+        let jobs =
+            map_variables(asset_def, Some(variables.clone()), &JobOptions::default()).unwrap();
+        assert_eq!(jobs[0].to, PathBuf::from("bin/setup.txt"));
+        assert_eq!(
+            jobs[0].opts.render,
+            Some(Render {
+                variables,
+                extensions: vec![".txt".to_owned()],
+                strict: true,
+            })
+        );
+    }
+
+    #[test]
+    fn render_needs_copy() {
+        let asset_def = AssetRelocationDef {
+            variables_in_use: vec![],
+            include: vec![],
+            defaults: OptionConfigs::default(),
+            base_dir: None,
+            jobs: vec![JobConfigs {
+                src: vec!["assets/setup.txt".to_owned()],
+                dst: "out/setup.txt".to_owned(),
+                options: OptionConfigs {
+                    todo: Some("hardlink".to_owned()),
+                    render: Some(true),
+                    ..Default::default()
+                },
+                base_dir: PathBuf::new(),
+            }],
+        };
+        let err = map_variables(asset_def, None, &JobOptions::default()).unwrap_err();
+        assert!(format!("{:#}", err).contains("\"render\" only works with copy"));
+    }
+
+    #[test]
+    fn no_operation() {
+        let asset_def = AssetRelocationDef {
+            variables_in_use: vec![],
+            include: vec![],
+            defaults: OptionConfigs::default(),
+            base_dir: None,
+            jobs: vec![JobConfigs {
+                src: vec!["this/is/var1/yes".to_owned()],
+                dst: "this/is/var2/yes".to_owned(),
+                options: OptionConfigs::default(),
+                base_dir: PathBuf::new(),
+            }],
+        };
+        let err = map_variables(asset_def, None, &JobOptions::default()).unwrap_err();
+        assert!(format!("{:#}", err).contains("job has no operation"));
+    }
+
+    #[test]
+    fn multiple_sources() {
+        let base = Path::new("./test_files").canonicalize().unwrap();
+        let json_jobs = load_jobs(
+            Path::new("./test_files/multiple_sources.json"),
+            Some(vec![(String::from("ProjectDir"), String::from("proj"))]),
+            &JobOptions::default(),
+        )
+        .unwrap();
+        let out_args = vec![Job {
+            op: Operation::Copy_,
+            from: vec![
+                base.join("proj/assets/setup.txt"),
+                base.join("proj/assets/content"),
+                base.join("proj/assets/*.xml"),
+            ],
+            to: base.join("out"),
+            opts: JobOptions::default(),
+        }];
+        assert_eq!(out_args, json_jobs);
+    }
+
+    #[test]
+    fn base_dir_setting() {
+        let home = PathBuf::from(expand_home("~").unwrap());
+        let json_args = |base: &str| {
+            load_jobs(
+                Path::new("./test_files/base_dir.json"),
+                Some(vec![(String::from("Base"), String::from(base))]),
+                &JobOptions::default(),
+            )
+            .unwrap()
+        };
+        let out_args = |base: PathBuf| {
+            vec![Job {
+                op: Operation::Copy_,
+                from: vec![base.join("assets/setup.txt")],
+                to: home.join("setup.txt"),
+                opts: JobOptions::default(),
+            }]
+        };
+        let test_files = Path::new("./test_files").canonicalize().unwrap();
+        assert_eq!(out_args(env::current_dir().unwrap()), json_args("cwd"));
+        assert_eq!(out_args(test_files.join("includes")), json_args("includes"));
+    }
+
+    #[test]
+    fn home_expansion() {
+        let home = expand_home("~").unwrap();
+        assert_ne!(home, "~");
+        assert_eq!(expand_home("~/assets").unwrap(), format!("{}/assets", home));
+        assert_eq!(expand_home("a/~/assets").unwrap(), "a/~/assets");
+        assert_eq!(expand_home("~assets").unwrap(), "~assets");
+    }
+
+    #[test]
+    fn cmdline_opts_as_defaults() {
+        let cmdline_opts = JobOptions {
+            verify: true,
+            hash: HashAlgo::XxHash,
+            on_conflict: OnConflict::Error,
+            ..Default::default()
+        };
+        let json_args = load_jobs(
+            Path::new("./test_files/job_defaults.json"),
+            None,
+            &cmdline_opts,
+        )
+        .unwrap();
+        let opts = &json_args[1].opts;
+        assert!(!opts.verify);
+        assert_eq!(opts.hash, HashAlgo::Sha256);
+        assert_eq!(opts.on_conflict, OnConflict::Overwrite);
+        let json_args = load_jobs(
+            Path::new("./test_files/multiple_sources.json"),
+            Some(vec![(String::from("ProjectDir"), String::from("proj"))]),
+            &JobOptions {
+                verify: true,
+                hash: HashAlgo::XxHash,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let opts = &json_args[0].opts;
+        assert!(opts.verify);
+        assert_eq!(opts.hash, HashAlgo::XxHash);
     }
 }
@@ -0,0 +1,43 @@
+//! copies, moves and hardlinks files, as described by [`Job`]s. jobs are built
+//! in code, or loaded from a json file:
+//!
+//! ```no_run
+//! use esycpy::{load_jobs, JobOptions, Run};
+//! use std::path::Path;
+//!
+//! let variables = Some(vec![(String::from("out"), String::from("build/assets"))]);
+//! let jobs = load_jobs(Path::new("assets.json"), variables, &JobOptions::default())?;
+//! let mut run = Run::new();
+//! run.jobs(jobs)?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! the esycpy binary runs jobs the same way, its options are documented in the
+//! README. cargo build scripts can use [`build`].
+
+pub mod build;
+pub mod checkpoint;
+mod checksum;
+pub mod depfile;
+pub mod events;
+mod hooks;
+mod job;
+pub mod journal;
+mod json_parser;
+mod naming;
+mod operations;
+pub mod progress;
+mod run;
+pub mod stats;
+mod template;
+mod text;
+pub mod watch;
+
+pub use job::{
+    Eol, HashAlgo, Job, JobOptions, OnConflict, OnEmpty, Operation, RenameRule, Render, Structure,
+    TextFilter, DEFAULT_RENDER_EXTENSIONS,
+};
+pub use json_parser::{load_job_file, load_jobs, resolve_variables, JobFile};
+pub use operations::Paths;
+pub use run::Run;
+pub use stats::Stats;
@@ -0,0 +1,221 @@
+use crate::args::LogOptions;
+use anyhow::Result;
+use log::{warn, LevelFilter, Log, Metadata, Record};
+use serde::Serialize;
+use simplelog::*;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// a log file bigger than this is rotated when a run starts
+const MAX_LOG_SIZE: u64 = 10 * 1024 * 1024;
+/// rotated files are kept as "<log>.1" to "<log>.3"
+const ROTATED_LOGS: usize = 3;
+
+/// terminal shows warnings by default, "$ESYCPY_LOG" overrides what the flags set.
+/// the log file, when there's one, gets everything.
+pub fn setup(opts: &LogOptions) -> Result<()> {
+    let term_level = env::var("ESYCPY_LOG")
+        .ok()
+        .and_then(|level| level.parse().ok())
+        .unwrap_or_else(|| verbosity_level(opts.verbosity));
+    let mut loggers: Vec<Box<dyn SharedLogger>> = vec![TermLogger::new(
+        term_level,
+        Config::default(),
+        TerminalMode::Mixed,
+    )];
+    let mut open_error = None;
+    if let Some(path) = &opts.file {
+        match open_log(path) {
+            Ok(file) if opts.json => loggers.push(JsonLogger::new(LevelFilter::Trace, file)),
+            Ok(file) => loggers.push(WriteLogger::new(
+                LevelFilter::Trace,
+                Config::default(),
+                file,
+            )),
+            Err(e) => open_error = Some((path, e)),
+        }
+    }
+    CombinedLogger::init(loggers)?;
+    // a run doesn't fail for its log file, it's logged on the terminal only
+    if let Some((path, e)) = open_error {
+        warn!("can't open log file {}: {}", path.display(), e);
+    }
+    Ok(())
+}
+
+/// -1 is quiet, 0 is the default, every --verbose adds 1
+fn verbosity_level(verbosity: i64) -> LevelFilter {
+    match verbosity {
+        i64::MIN..=-1 => LevelFilter::Error,
+        0 => LevelFilter::Warn,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+/// appends to the log file, after rotating it when it's too big
+fn open_log(path: &Path) -> Result<File> {
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    if fs::metadata(path)
+        .map(|m| m.len() > MAX_LOG_SIZE)
+        .unwrap_or(false)
+    {
+        for n in (1..ROTATED_LOGS).rev() {
+            let older = rotated(path, n);
+            if older.exists() {
+                fs::rename(older, rotated(path, n + 1))?;
+            }
+        }
+        fs::rename(path, rotated(path, 1))?;
+    }
+    Ok(OpenOptions::new().create(true).append(true).open(path)?)
+}
+
+fn rotated(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+/// one json object per line, for log collectors
+struct JsonLogger {
+    level: LevelFilter,
+    file: Mutex<File>,
+}
+
+#[derive(Serialize)]
+struct JsonLine<'a> {
+    time: f64,
+    level: String,
+    target: &'a str,
+    message: String,
+}
+
+impl JsonLogger {
+    fn new(level: LevelFilter, file: File) -> Box<Self> {
+        Box::new(Self {
+            level,
+            file: Mutex::new(file),
+        })
+    }
+}
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = JsonLine {
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64(),
+            level: record.level().to_string(),
+            target: record.target(),
+            message: record.args().to_string(),
+        };
+        if let Ok(line) = serde_json::to_string(&line) {
+            let mut file = self.file.lock().unwrap();
+            let _ = writeln!(file, "{}", line);
+        }
+    }
+
+    fn flush(&self) {
+        let _ = self.file.lock().unwrap().flush();
+    }
+}
+
+impl SharedLogger for JsonLogger {
+    fn level(&self) -> LevelFilter {
+        self.level
+    }
+
+    fn config(&self) -> Option<&Config> {
+        None
+    }
+
+    fn as_log(self: Box<Self>) -> Box<dyn Log> {
+        Box::new(*self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+    use tempfile::TempDir;
+
+    #[test]
+    fn verbosity() {
+        assert_eq!(verbosity_level(-1), LevelFilter::Error);
+        assert_eq!(verbosity_level(0), LevelFilter::Warn);
+        assert_eq!(verbosity_level(2), LevelFilter::Debug);
+        assert_eq!(verbosity_level(5), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn log_rotated() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let path = tmp_dir.path().join("logs").join("esycpy.log");
+        writeln!(open_log(&path)?, "first")?;
+        writeln!(open_log(&path)?, "second")?;
+        assert_eq!(fs::read_to_string(&path)?, "first\nsecond\n");
+        for run in 1..=ROTATED_LOGS + 2 {
+            let mut file = open_log(&path)?;
+            writeln!(file, "run {}", run)?;
+            file.set_len(MAX_LOG_SIZE + 1)?;
+        }
+        let first_line = |path: PathBuf| -> Result<String> {
+            let text = fs::read(path)?;
+            Ok(String::from_utf8_lossy(&text)
+                .lines()
+                .next()
+                .unwrap()
+                .to_owned())
+        };
+        assert_eq!(first_line(path.clone())?, "run 5");
+        assert_eq!(first_line(rotated(&path, 1))?, "run 4");
+        assert_eq!(first_line(rotated(&path, 3))?, "run 2");
+        assert!(!rotated(&path, 4).exists());
+        Ok(())
+    }
+
+    #[test]
+    fn json_lines() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let path = tmp_dir.path().join("esycpy.log");
+        let logger = JsonLogger::new(LevelFilter::Info, File::create(&path)?);
+        let args = format_args!("copied {}", 3);
+        logger.log(
+            &Record::builder()
+                .args(args)
+                .level(Level::Info)
+                .target("esycpy")
+                .build(),
+        );
+        logger.log(
+            &Record::builder()
+                .args(args)
+                .level(Level::Debug)
+                .target("esycpy")
+                .build(),
+        );
+        let text = fs::read_to_string(&path)?;
+        assert_eq!(text.lines().count(), 1);
+        let line: serde_json::Value = serde_json::from_str(text.trim())?;
+        assert_eq!(line["level"], "INFO");
+        assert_eq!(line["message"], "copied 3");
+        Ok(())
+    }
+}
@@ -1,60 +1,98 @@
 use anyhow::Result;
+use esycpy::checkpoint::Checkpoint;
+use esycpy::depfile;
+use esycpy::events::Events;
+use esycpy::journal::{self, Journal};
+use esycpy::progress::Progress;
+use esycpy::watch;
+use esycpy::{load_job_file, Run};
 use git_version::git_version;
-use log::trace;
-use simplelog::*;
-use std::fs::File;
+use log::{debug, info, trace};
+use std::path::Path;
 
 mod args;
-mod json_parser;
-mod operations;
+mod logging;
 
 use args::{get_args, ArgsType};
-use json_parser::get_json_args;
-use operations::FileOp;
 
 fn main() -> Result<()> {
-    print_git_version();
-    setup_logger()?;
+    let (args, run_opts) = get_args();
+    logging::setup(&run_opts.log)?;
     log_panics::init();
-    let args = get_args();
-    eprintln!("input: {:?}", args);
+    log_git_version();
+    debug!("input: {:?}", args);
     trace!("{:#?}", args);
-    match args {
-        ArgsType::CmdLine {
-            op: _,
-            from: _,
-            to: _,
-        } => {
-            let file_op = FileOp::from(args);
-            file_op.process()?;
-        }
+    trace!("{:#?}", run_opts);
+    if let ArgsType::Undo { run } = &args {
+        return undo(&run_opts.journal_dir, run.as_deref());
+    }
+    if let ArgsType::Watch {
+        json_file,
+        variables,
+        opts,
+        watch,
+    } = &args
+    {
+        return watch::watch(json_file, variables.clone(), opts, watch);
+    }
+    let quiet = run_opts.log.verbosity < 0;
+    let mut run = Run::new().progress(Progress::new(quiet));
+    if run_opts.journal {
+        run = run.journal(Journal::new(&run_opts.journal_dir));
+    }
+    if run_opts.events {
+        run = run.events(Events::new());
+    }
+    let (jobs, json_files) = match args {
         ArgsType::Json {
-            json_file: _,
-            variables: _,
+            json_file,
+            variables,
+            opts,
         } => {
-            let v_args = get_json_args(args);
-            for args in v_args {
-                let file_op = operations::FileOp::from(args);
-                file_op.process()?;
-            }
+            let job_file = load_job_file(&json_file, variables, &opts)?;
+            run = run.checkpoint(Checkpoint::open(
+                &json_file,
+                &format!("{:?}", job_file.jobs),
+                run_opts.resume,
+            )?);
+            (job_file.jobs, job_file.files)
+        }
+        ArgsType::CmdLine(job) => (vec![job], Vec::new()),
+        ArgsType::Watch { .. } | ArgsType::Undo { .. } => unreachable!(),
+    };
+    let depfile = match &run_opts.depfile {
+        Some(path) => {
+            // the depfile's target is what the build system expects the run to produce
+            let targets = match &run_opts.stamp {
+                Some(stamp) => vec![stamp.clone()],
+                None => jobs.iter().map(|job| job.to.clone()).collect(),
+            };
+            Some((path, targets, depfile::inputs(&json_files, &jobs)?))
         }
+        None => None,
+    };
+    let result = run.jobs(jobs);
+    run.report().print(quiet);
+    if let Some(path) = &run_opts.report {
+        run.report().write(path)?;
+    }
+    result?;
+    if let Some((path, targets, inputs)) = depfile {
+        depfile::write(path, &targets, &inputs)?;
+    }
+    if let Some(stamp) = &run_opts.stamp {
+        depfile::stamp(stamp)?;
     }
     Ok(())
 }
 
-fn setup_logger() -> Result<()> {
-    CombinedLogger::init(vec![
-        TermLogger::new(LevelFilter::Warn, Config::default(), TerminalMode::Mixed),
-        WriteLogger::new(
-            LevelFilter::Trace,
-            Config::default(),
-            File::create(".esycpy.log")?,
-        ),
-    ])?;
+fn undo(journal_dir: &Path, run: Option<&str>) -> Result<()> {
+    let run = journal::undo(journal_dir, run)?;
+    println!("reverted run {}", run);
     Ok(())
 }
 
-fn print_git_version() {
+fn log_git_version() {
     const GIT_VERSION: &str = git_version!();
-    eprintln!("Running esycpy, version: {}", GIT_VERSION);
+    info!("Running esycpy, version: {}", GIT_VERSION);
 }
@@ -0,0 +1,243 @@
+//! names of destination files and dirs, from rename rules and templates of
+//! file metadata like "{stem}_{index}.{ext}" or "archive/{mtime:%Y}/{ext}"
+
+use crate::job::RenameRule;
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Local};
+use regex::Regex;
+use std::ffi::OsStr;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf, MAIN_SEPARATOR};
+
+enum Rule {
+    Regex(Regex, String),
+    Lower,
+    Upper,
+    Template(String),
+}
+
+/// rename rules of a job, with their patterns compiled
+pub struct Renamer {
+    rules: Vec<Rule>,
+}
+
+impl Renamer {
+    pub fn new(rules: &[RenameRule]) -> Result<Self> {
+        let mut compiled = Vec::new();
+        for rule in rules {
+            compiled.push(match rule {
+                RenameRule::Regex { pattern, to } => {
+                    let re = Regex::new(pattern)
+                        .map_err(|e| anyhow!("invalid rename pattern {}: {}", pattern, e))?;
+                    Rule::Regex(re, to.clone())
+                }
+                RenameRule::Lower => Rule::Lower,
+                RenameRule::Upper => Rule::Upper,
+                RenameRule::Template(template) => Rule::Template(template.clone()),
+            });
+        }
+        Ok(Self { rules: compiled })
+    }
+
+    /// `dst` with its file name renamed. `src` is the file placed there, and
+    /// `index` counts the files of the job from 1
+    pub fn rename(&self, src: &Path, dst: &Path, index: usize) -> Result<PathBuf> {
+        let mut name = dst
+            .file_name()
+            .expect("file name must be present")
+            .to_string_lossy()
+            .into_owned();
+        for rule in &self.rules {
+            name = match rule {
+                Rule::Regex(re, to) => re.replace(&name, to.as_str()).into_owned(),
+                Rule::Lower => name.to_lowercase(),
+                Rule::Upper => name.to_uppercase(),
+                Rule::Template(template) => {
+                    let file = FileInfo {
+                        name: &name,
+                        src,
+                        index: Some(index),
+                    };
+                    expand(template, &file, true)?
+                }
+            };
+        }
+        if name.is_empty() || name == "." || name == ".." || name.contains(['/', MAIN_SEPARATOR]) {
+            bail!("{} is renamed to invalid name \"{}\"", src.display(), name);
+        }
+        Ok(dst.with_file_name(name))
+    }
+}
+
+/// whether `dst` has placeholders which are put in for every file
+pub fn has_placeholders(dst: &Path) -> bool {
+    let re = Regex::new(r"\{(mtime(:[^}]*)?|size_bucket|ext|stem|parent)\}").unwrap();
+    re.is_match(&dst.to_string_lossy())
+}
+
+/// `dst` with metadata of `src` put in its placeholders, like
+/// "archive/{mtime:%Y/%m}/{ext}". other braces are kept as they are
+pub fn expand_dst(dst: &Path, src: &Path) -> Result<PathBuf> {
+    let name = src.file_name().unwrap_or_default().to_string_lossy();
+    let file = FileInfo {
+        name: &name,
+        src,
+        index: None,
+    };
+    // a format like "%Y/%m" makes dirs, so it's expanded as a whole
+    let expanded = expand(&dst.to_string_lossy(), &file, false)?;
+    Ok(PathBuf::from(expanded))
+}
+
+/// the file whose metadata goes in placeholders
+struct FileInfo<'a> {
+    /// name so far, "{stem}" and "{ext}" are of it
+    name: &'a str,
+    src: &'a Path,
+    /// only renamed files are counted
+    index: Option<usize>,
+}
+
+/// puts metadata of the file in the placeholders of `template`. "{mtime}" is
+/// "{mtime:%Y-%m-%d}". unknown placeholders are kept, or are an error when
+/// `strict`
+fn expand(template: &str, file: &FileInfo, strict: bool) -> Result<String> {
+    let re = Regex::new(r"\{(\w+)(?::([^}]*))?\}").unwrap();
+    let lossy = |s: Option<&OsStr>| s.map(|s| s.to_string_lossy().into_owned());
+    let mut expanded = String::new();
+    let mut last = 0;
+    for caps in re.captures_iter(template) {
+        let placeholder = caps.get(0).unwrap();
+        expanded.push_str(&template[last..placeholder.start()]);
+        last = placeholder.end();
+        let name = Path::new(file.name);
+        let value = match (&caps[1], caps.get(2), file.index) {
+            ("stem", None, _) => lossy(name.file_stem()).unwrap_or_default(),
+            ("ext", None, _) => lossy(name.extension()).unwrap_or_default(),
+            ("parent", None, _) => {
+                lossy(file.src.parent().and_then(|p| p.file_name())).unwrap_or_default()
+            }
+            ("index", None, Some(index)) => index.to_string(),
+            ("size_bucket", None, _) => size_bucket(fs::metadata(file.src)?.len()).to_owned(),
+            ("mtime", format, _) => {
+                let mtime: DateTime<Local> = fs::metadata(file.src)?.modified()?.into();
+                let format = format.map_or("%Y-%m-%d", |f| f.as_str());
+                let mut value = String::new();
+                write!(value, "{}", mtime.format(format))
+                    .map_err(|_| anyhow!("invalid mtime format in {}", template))?;
+                value
+            }
+            _ if strict => bail!(
+                "unknown placeholder {} in {}",
+                placeholder.as_str(),
+                template
+            ),
+            _ => placeholder.as_str().to_owned(),
+        };
+        expanded.push_str(&value);
+    }
+    expanded.push_str(&template[last..]);
+    Ok(expanded)
+}
+
+/// "tiny" below 100 KiB, "small" below 1 MiB, "medium" below 100 MiB,
+/// "large" below 1 GiB, else "huge"
+fn size_bucket(size: u64) -> &'static str {
+    const KIB: u64 = 1024;
+    const MIB: u64 = 1024 * KIB;
+    match size {
+        s if s < 100 * KIB => "tiny",
+        s if s < MIB => "small",
+        s if s < 100 * MIB => "medium",
+        s if s < 1024 * MIB => "large",
+        _ => "huge",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+    use tempfile::TempDir;
+
+    #[test]
+    fn names_renamed() -> Result<()> {
+        let src = Path::new("assets/Tex/Rock_hi.PNG");
+        let dst = Path::new("out/Rock_hi.PNG");
+        let rename = |rules: &[RenameRule]| Renamer::new(rules)?.rename(src, dst, 3);
+        assert_eq!(
+            rename(&[
+                RenameRule::Regex {
+                    pattern: r"(?i)(.*)_hi\.png".to_owned(),
+                    to: "$1.png".to_owned(),
+                },
+                RenameRule::Lower,
+            ])?,
+            Path::new("out/rock.png")
+        );
+        assert_eq!(rename(&[RenameRule::Upper])?, Path::new("out/ROCK_HI.PNG"));
+        assert_eq!(
+            rename(&[
+                RenameRule::Lower,
+                RenameRule::Template("{parent}_{stem}_{index}.{ext}".to_owned()),
+            ])?,
+            Path::new("out/Tex_rock_hi_3.png")
+        );
+        assert!(rename(&[RenameRule::Template("{size}".to_owned())]).is_err());
+        assert!(rename(&[RenameRule::Template("a/b".to_owned())]).is_err());
+        assert!(Renamer::new(&[RenameRule::Regex {
+            pattern: "(".to_owned(),
+            to: String::new(),
+        }])
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn mtime_formatted() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let src = tmp_dir.path().join("capture.log");
+        fs::write(&src, "log")?;
+        // noon, so it's the same day in every time zone
+        let mtime = SystemTime::UNIX_EPOCH
+            + Duration::from_secs(1_700_000_000 / 86400 * 86400)
+            + Duration::from_secs(12 * 3600);
+        fs::OpenOptions::new()
+            .write(true)
+            .open(&src)?
+            .set_modified(mtime)?;
+        let renamer = Renamer::new(&[RenameRule::Template(
+            "{mtime}_{stem}_{mtime:%m}.{ext}".to_owned(),
+        )])?;
+        assert_eq!(
+            renamer.rename(&src, Path::new("out/capture.log"), 1)?,
+            Path::new("out/2023-11-14_capture_11.log")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn dst_expanded() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let src = tmp_dir.path().join("build.LOG");
+        fs::write(&src, "log")?;
+        let mtime = SystemTime::UNIX_EPOCH
+            + Duration::from_secs(1_700_000_000 / 86400 * 86400)
+            + Duration::from_secs(12 * 3600);
+        fs::OpenOptions::new()
+            .write(true)
+            .open(&src)?
+            .set_modified(mtime)?;
+        let dst = Path::new("/archive/{mtime:%Y/%m}/{ext}/{size_bucket}/{OutDir}");
+        assert!(has_placeholders(dst));
+        assert!(!has_placeholders(Path::new("/archive/{OutDir}")));
+        assert_eq!(
+            expand_dst(dst, &src)?,
+            Path::new("/archive/2023/11/LOG/tiny/{OutDir}")
+        );
+        assert_eq!(size_bucket(100 * 1024), "small");
+        assert_eq!(size_bucket(5 << 30), "huge");
+        Ok(())
+    }
+}
@@ -1,82 +1,232 @@
-use crate::args::{ArgsType, Operation};
-use anyhow::Result;
-use log::trace;
+use crate::checkpoint::Checkpoint;
+use crate::checksum;
+use crate::events::{self, Event, Events};
+use crate::hooks;
+use crate::job::{Job, JobOptions, OnConflict, OnEmpty, Operation, Structure};
+use crate::journal::{self, Journal};
+use crate::naming::{self, Renamer};
+use crate::progress::Progress;
+use crate::stats::{SlowFile, Stats};
+use crate::template;
+use crate::text;
+use anyhow::{bail, Context, Result};
+use log::{trace, warn};
 use pathdiff::diff_paths;
-use regex::Regex;
-use std::fs;
-use std::path::{Path, PathBuf};
+use regex::bytes::Regex;
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf, MAIN_SEPARATOR};
+use std::rc::Rc;
+use std::time::Instant;
 use walkdir::{DirEntry, WalkDir};
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct FileOp {
     op: Option<Operation>,
     p: Paths,
     f_type: Option<FileType>,
+    opts: JobOptions,
+    journal: Option<Rc<Journal>>,
+    checkpoint: Option<Rc<Checkpoint>>,
+    progress: Option<Rc<Progress>>,
+    events: Option<Rc<Events>>,
 }
 
+/// what happened to one source path
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Transferred { overwritten: bool },
+    Skipped(&'static str),
+}
+
+/// a source file or dir, and where it's placed
 #[derive(Debug, Clone, Default, Eq, PartialEq, Ord, PartialOrd)]
 pub struct Paths {
-    from: PathBuf,
-    to: PathBuf,
+    pub from: PathBuf,
+    pub to: PathBuf,
 }
 
-#[derive(Debug, PartialEq)]
+type Rename = dyn Fn(&Path, &Path) -> io::Result<()>;
+
+/// smaller files are copied in one go, progress shows them once they're done
+const PROGRESS_SIZE: u64 = 8 * 1024 * 1024;
+
+#[derive(Debug, PartialEq, Clone)]
 enum FileType {
     File,
     Dir,
-    Filter(String),
+    Filter(OsString),
 }
 
-impl Default for FileOp {
-    fn default() -> Self {
+impl FileOp {
+    /// one file operation is created for every source. when there are many sources,
+    /// or destination is a dir, plain files are placed inside the destination.
+    /// with many sources, dirs are placed inside it too, like `cp -r a b dst/`,
+    /// so files of different dirs don't overwrite each other.
+    pub fn from(job: Job) -> Vec<Self> {
+        let Job { op, from, to, opts } = job;
+        let many = from.len() > 1;
+        // a templated dst is a dir which files are sorted into, even for one file
+        let into_dir = many || FileOp::is_dir_path(&to) || naming::has_placeholders(&to);
+        from.into_iter()
+            .map(|from| {
+                let file_op = FileOp::new(op, from, to.clone(), opts.clone());
+                match (&file_op.f_type, into_dir) {
+                    (Some(FileType::File), true) => {
+                        let to = to.join(file_op.p.from.file_name().unwrap());
+                        FileOp::new(op, file_op.p.from, to, opts.clone())
+                    }
+                    (Some(FileType::Dir), _) if many => {
+                        let to = to.join(file_op.p.from.file_name().unwrap());
+                        FileOp::new(op, file_op.p.from, to, opts.clone())
+                    }
+                    _ => file_op,
+                }
+            })
+            .collect()
+    }
+
+    /// an existing dir, or a path ending with a separator like "out/"
+    fn is_dir_path(path: &Path) -> bool {
+        let path_str = path.to_string_lossy();
+        path.is_dir() || path_str.ends_with('/') || path_str.ends_with(MAIN_SEPARATOR)
+    }
+
+    fn new(op: Operation, from: PathBuf, to: PathBuf, opts: JobOptions) -> Self {
+        let mut from = from;
+        let file_path = Path::new(&from);
+        let file_name = file_path.file_name().expect("file name must be present");
+        let f_type: Option<FileType>;
+        if file_name.as_encoded_bytes().contains(&b'*') {
+            f_type = Some(FileType::Filter(file_name.to_owned()));
+            from = file_path.parent().unwrap().to_owned();
+        } else if file_path.is_dir() {
+            f_type = Some(FileType::Dir);
+        } else {
+            f_type = Some(FileType::File);
+        }
         Self {
-            op: None,
-            f_type: None,
-            p: Paths::default(),
+            op: Some(op),
+            p: Paths { from, to },
+            f_type,
+            opts,
+            journal: None,
+            checkpoint: None,
+            progress: None,
+            events: None,
         }
     }
-}
 
-impl FileOp {
-    pub fn from(arg_paths: ArgsType) -> Self {
-        let file_op = match arg_paths {
-            ArgsType::CmdLine { op, from, to } => {
-                let mut from = from;
-                let to = to;
-                let file_path = Path::new(&from);
-                let file_name = file_path
-                    .file_name()
-                    .expect("file name must be present")
-                    .to_str()
-                    .unwrap();
-                let f_type: Option<FileType>;
-                if file_name.contains('*') {
-                    f_type = Some(FileType::Filter(file_name.to_owned()));
-                    from = file_path.parent().unwrap().to_owned();
-                } else if file_path.is_dir() {
-                    f_type = Some(FileType::Dir);
-                } else {
-                    f_type = Some(FileType::File);
-                }
-                Self {
-                    op: Some(op),
-                    p: Paths { from, to },
-                    f_type,
-                }
+    /// changes made by `process` are recorded in the journal, so they can be undone
+    pub fn set_journal(&mut self, journal: Rc<Journal>) {
+        self.journal = Some(journal);
+    }
+
+    /// files already done in the checkpoint are skipped, and done files are added to it
+    pub fn set_checkpoint(&mut self, checkpoint: Rc<Checkpoint>) {
+        self.checkpoint = Some(checkpoint);
+    }
+
+    fn is_done(&self, src: &Path) -> bool {
+        self.checkpoint
+            .as_ref()
+            .is_some_and(|checkpoint| checkpoint.is_file_done(src))
+    }
+
+    fn copy_file(&self, src: &Path, dst: &Path, out: &Path) -> Result<()> {
+        let progress = self.progress.as_deref();
+        match (&self.checkpoint, progress) {
+            (Some(checkpoint), _) => checkpoint.copy(src, dst, out, progress),
+            (None, Some(progress)) if fs::metadata(src)?.len() >= PROGRESS_SIZE => {
+                let mut src_file = File::open(src)?;
+                let mut out_file = File::create(out)?;
+                progress.copy(&mut src_file, &mut out_file)?;
+                drop(out_file);
+                fs::set_permissions(out, src_file.metadata()?.permissions())?;
+                Ok(())
             }
-            _ => unreachable!(),
-        };
-        file_op
+            (None, _) => {
+                let _ = fs::copy(src, out)?;
+                Ok(())
+            }
+        }
+    }
+
+    pub fn set_progress(&mut self, progress: Rc<Progress>) {
+        self.progress = Some(progress);
+    }
+
+    fn progress<F: FnOnce(&Progress)>(&self, update: F) {
+        if let Some(progress) = &self.progress {
+            update(progress);
+        }
+    }
+
+    pub fn set_events(&mut self, events: Rc<Events>) {
+        self.events = Some(events);
+    }
+
+    fn emit<F: FnOnce() -> Event>(&self, event: F) {
+        if let Some(events) = &self.events {
+            events.emit(event());
+        }
+    }
+
+    fn journal<F>(&self, record: F) -> Result<()>
+    where
+        F: FnOnce(&Journal) -> Result<()>,
+    {
+        match &self.journal {
+            Some(journal) => record(journal),
+            None => Ok(()),
+        }
+    }
+
+    fn create_dir_all(&self, dir: &Path) -> Result<()> {
+        match &self.journal {
+            Some(journal) => journal.create_dir_all(dir),
+            None => Ok(fs::create_dir_all(dir)?),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn process(&self) -> Result<Stats> {
+        let (stats, result) = self.process_counted();
+        result.map(|()| stats)
     }
 
-    pub fn process(&self) -> Result<()> {
+    /// like `process`, but what was done before a failure is still counted
+    pub(crate) fn process_counted(&self) -> (Stats, Result<()>) {
         trace!("processing {:?}", self);
-        match &self.f_type {
-            Some(FileType::File) => self.file_op(&[self.p.clone()])?,
+        let plan = match self.plan() {
+            Ok(plan) => plan,
+            Err(e) => return (Stats::default(), Err(e)),
+        };
+        let (stats, result) = self.file_op_counted(&plan);
+        if result.is_ok() && stats.matched == 0 {
+            return (stats, self.nothing_matched());
+        }
+        (stats, result)
+    }
+
+    /// what `process` transfers: the source itself, or files matched in it
+    pub fn plan(&self) -> Result<Vec<Paths>> {
+        let paths = match &self.f_type {
+            // a missing file matches nothing, like a filter without matches
+            Some(FileType::File) if !self.p.from.exists() => Vec::new(),
+            // placeholders are only in the dir, not in the file name
+            Some(FileType::File) if naming::has_placeholders(&self.p.to) => vec![Paths {
+                from: self.p.from.clone(),
+                to: naming::expand_dst(self.p.to.parent().unwrap(), &self.p.from)?
+                    .join(self.p.to.file_name().unwrap()),
+            }],
+            Some(FileType::File) => vec![self.p.clone()],
             Some(FileType::Dir) => self.dir_to_dir()?,
             Some(FileType::Filter(file_name)) => {
                 let mut only_cur_dir = true;
-                if file_name.contains("**") {
+                if FileOp::contains_bytes(file_name.as_encoded_bytes(), b"**") {
                     only_cur_dir = false;
                 }
                 let ext = FileOp::get_ext(file_name)?;
@@ -84,33 +234,264 @@ impl FileOp {
                     if ext.is_empty() {
                         true
                     } else {
-                        f.file_name().to_str().unwrap().contains(&ext)
+                        FileOp::contains_bytes(f.file_name().as_encoded_bytes(), &ext)
                     }
                 };
-                let v_paths = self.get_src_dst_paths(filter, only_cur_dir, !ext.is_empty());
-                self.file_op(&v_paths)?;
+                self.get_src_dst_paths(filter, only_cur_dir, !ext.is_empty())?
             }
             None => unreachable!(),
+        };
+        Ok(paths)
+    }
+
+    /// transfers only `paths`, which are part of the plan
+    pub fn process_paths(&self, paths: &[Paths]) -> Result<Stats> {
+        self.file_op(paths)
+    }
+
+    /// dir which holds the sources, and whether files in its sub dirs are sources too
+    pub fn root(&self) -> (&Path, bool) {
+        match &self.f_type {
+            Some(FileType::File) => (self.p.from.parent().unwrap(), false),
+            Some(FileType::Dir) => (&self.p.from, true),
+            Some(FileType::Filter(file_name)) => (
+                &self.p.from,
+                FileOp::contains_bytes(file_name.as_encoded_bytes(), b"**"),
+            ),
+            None => unreachable!(),
+        }
+    }
+
+    /// source files and dirs which `process` reads. dirs are listed too, as a file
+    /// added to or removed from a dir changes only the dir.
+    pub fn inputs(&self) -> Result<Vec<PathBuf>> {
+        let mut inputs: Vec<PathBuf> = Vec::new();
+        for p in self.plan()? {
+            inputs.extend(
+                WalkDir::new(&p.from)
+                    .into_iter()
+                    .filter_map(|f| f.ok())
+                    .map(|f| f.into_path()),
+            );
+        }
+        if self.f_type != Some(FileType::File) {
+            let (root, recursive) = self.root();
+            let excludes = self.excludes();
+            let mut dir_walker = WalkDir::new(root);
+            if !recursive {
+                dir_walker = dir_walker.max_depth(0);
+            }
+            inputs.extend(
+                dir_walker
+                    .into_iter()
+                    .filter_entry(|f| !self.is_excluded(&excludes, f.path()))
+                    .filter_map(|f| f.ok())
+                    .filter(|f| f.file_type().is_dir())
+                    .map(|f| f.into_path()),
+            );
+        }
+        inputs.sort();
+        inputs.dedup();
+        Ok(inputs)
+    }
+
+    /// a source which matches nothing is often a typo in the job
+    fn nothing_matched(&self) -> Result<()> {
+        let src = match &self.f_type {
+            Some(FileType::Filter(file_name)) => self.p.from.join(file_name),
+            _ => self.p.from.clone(),
+        };
+        match self.opts.on_empty {
+            OnEmpty::Ok => trace!("no files matched {:?}", src),
+            OnEmpty::Warn => warn!("no files matched {}", src.display()),
+            OnEmpty::Error => bail!("no files matched {}", src.display()),
         }
         Ok(())
     }
 
-    fn get_ext(file_name: &str) -> Result<String> {
-        let re = Regex::new(r"\**(.*)$")?;
-        let cap = re.captures(file_name).unwrap();
+    /// a single file source isn't staged, its destination is a file
+    pub(crate) fn is_file(&self) -> bool {
+        self.f_type == Some(FileType::File)
+    }
+
+    /// moved sources are gone from where they were once the op ran
+    pub(crate) fn is_move(&self) -> bool {
+        self.op == Some(Operation::Move)
+    }
+
+    /// the file op placed in `staging` instead of `dst`, which its destination is in
+    pub(crate) fn staged_in(mut self, dst: &Path, staging: &Path) -> Self {
+        self.p.to = match self.p.to.strip_prefix(dst) {
+            Ok(rel) if rel.as_os_str().is_empty() => staging.to_owned(),
+            Ok(rel) => staging.join(rel),
+            Err(_) => self.p.to,
+        };
+        self
+    }
+
+    /// the dir where a staged job builds `dst`, next to it. one left by an
+    /// earlier run is removed, unless it may hold files that run moved
+    pub(crate) fn staging_dir(dst: &Path, moves: bool) -> Result<PathBuf> {
+        if naming::has_placeholders(dst) {
+            bail!("staged doesn't work with placeholders in the destination");
+        }
+        let staging = FileOp::sibling_path(dst, ".", ".esycpy-staging");
+        if staging.exists() && moves {
+            bail!(
+                "staging dir left by an earlier run may hold moved files, check and remove it: {}",
+                staging.display()
+            );
+        }
+        if staging.exists() {
+            trace!("remove staging dir left by an earlier run: {:?}", staging);
+            fs::remove_dir_all(&staging)?;
+        }
+        Ok(staging)
+    }
+
+    /// moves staging dir to dst. the replaced dst is kept as "<dst>.prev" or removed.
+    /// the swap is journaled as moves, so undo takes dst back to the staging dir,
+    /// from where the files in it are undone like any others
+    pub(crate) fn swap_in(
+        staging: &Path,
+        dst: &Path,
+        keep_previous: bool,
+        journal: Option<&Journal>,
+    ) -> Result<()> {
+        if !dst.exists() {
+            let parent = dst.parent().unwrap();
+            match journal {
+                Some(journal) => journal.create_dir_all(parent)?,
+                None => fs::create_dir_all(parent)?,
+            }
+            fs::rename(staging, dst)?;
+            return journal.map_or(Ok(()), |j| j.moved(staging, dst));
+        }
+        if !dst.is_dir() {
+            bail!("staged destination isn't a dir: {}", dst.display());
+        }
+        let old = FileOp::sibling_path(dst, ".", ".esycpy-old");
+        match FileOp::exchange(staging, dst) {
+            // the old dir is moved off the staging path, which undo needs free
+            Ok(()) => fs::rename(staging, &old)?,
+            Err(e) => {
+                trace!("can't exchange dirs, renaming one by one: {}", e);
+                fs::rename(dst, &old)?;
+                fs::rename(staging, dst)?;
+            }
+        }
+        if let Some(journal) = journal {
+            journal.moved(dst, &old)?;
+            journal.moved(staging, dst)?;
+        }
+        if keep_previous {
+            let prev = FileOp::sibling_path(dst, "", ".prev");
+            if prev.exists() {
+                FileOp::remove_dir_all(&prev, journal)?;
+            }
+            fs::rename(&old, &prev)?;
+            if let Some(journal) = journal {
+                journal.moved(&old, &prev)?;
+            }
+        } else {
+            FileOp::remove_dir_all(&old, journal)?;
+        }
+        Ok(())
+    }
+
+    /// with a journal, the dir is moved into its backups instead
+    fn remove_dir_all(dir: &Path, journal: Option<&Journal>) -> Result<()> {
+        match journal {
+            Some(journal) => journal.stash(dir),
+            None => Ok(fs::remove_dir_all(dir)?),
+        }
+    }
+
+    /// swaps both paths in one step
+    #[cfg(target_os = "linux")]
+    fn exchange(from: &Path, to: &Path) -> io::Result<()> {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+        let from = CString::new(from.as_os_str().as_bytes())?;
+        let to = CString::new(to.as_os_str().as_bytes())?;
+        // SAFETY: both are valid nul terminated paths, which live across the call
+        let ret = unsafe {
+            libc::renameat2(
+                libc::AT_FDCWD,
+                from.as_ptr(),
+                libc::AT_FDCWD,
+                to.as_ptr(),
+                libc::RENAME_EXCHANGE,
+            )
+        };
+        if ret == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn exchange(_from: &Path, _to: &Path) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "exchanging paths is only supported on linux",
+        ))
+    }
+
+    fn sibling_path(path: &Path, prefix: &str, suffix: &str) -> PathBuf {
+        let mut name = OsString::from(prefix);
+        name.push(path.file_name().expect("file name must be present"));
+        name.push(suffix);
+        path.with_file_name(name)
+    }
+
+    /// file names are compared as bytes, so names which aren't valid utf-8 work too
+    fn get_ext<S: AsRef<OsStr>>(file_name: S) -> Result<Vec<u8>> {
+        let re = Regex::new(r"(?s-u)\**(.*)$")?;
+        let cap = re.captures(file_name.as_ref().as_encoded_bytes()).unwrap();
         Ok(cap[1].to_owned())
     }
 
-    fn dir_to_dir(&self) -> Result<()> {
+    fn contains_bytes(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack.windows(needle.len()).any(|w| w == needle)
+    }
+
+    fn dir_to_dir(&self) -> Result<Vec<Paths>> {
+        let whole_dir = self.opts.exclude.is_empty()
+            && self.opts.structure != Structure::Flatten
+            && self.opts.rename.is_empty()
+            && !naming::has_placeholders(&self.p.to);
         match self.op {
-            Some(Operation::Move) => self.file_op(&[self.p.clone()])?,
-            Some(Operation::Hardlink) | Some(Operation::Copy_) => {
-                let v_paths = self.get_src_dst_paths(|f| f.path().is_file(), false, false);
-                self.file_op(&v_paths)?;
+            Some(Operation::Move) if whole_dir => Ok(vec![self.p.clone()]),
+            Some(Operation::Move) | Some(Operation::Hardlink) | Some(Operation::Copy_) => {
+                self.get_src_dst_paths(|f| f.path().is_file(), false, false)
             }
             None => unreachable!(),
         }
-        Ok(())
+    }
+
+    /// exclude patterns are matched against every component of the path
+    /// relative to the source dir, so both file and dir names can be excluded.
+    fn is_excluded(&self, excludes: &[Regex], src: &Path) -> bool {
+        let rel_path = diff_paths(src, &self.p.from).unwrap_or_else(|| src.to_owned());
+        rel_path.components().any(|c| {
+            let name = c.as_os_str().as_encoded_bytes();
+            excludes.iter().any(|re| re.is_match(name))
+        })
+    }
+
+    fn excludes(&self) -> Vec<Regex> {
+        self.opts
+            .exclude
+            .iter()
+            .map(|e| FileOp::pattern_to_regex(e).expect("invalid exclude pattern"))
+            .collect()
+    }
+
+    fn pattern_to_regex(pattern: &str) -> Result<Regex> {
+        let re = regex::escape(pattern).replace(r"\*", "(?s-u:.)*");
+        Ok(Regex::new(&format!("^{}$", re))?)
     }
 
     fn fix_offset(p: &Paths, new_src: &Path) -> PathBuf {
@@ -123,66 +504,354 @@ impl FileOp {
         fname_filter: F,
         only_cur_dir: bool,
         ext_specified: bool,
-    ) -> Vec<Paths>
+    ) -> Result<Vec<Paths>>
     where
         F: Fn(&DirEntry) -> bool,
     {
         let mut paths: Vec<Paths> = Vec::new();
+        let excludes = self.excludes();
+        let flatten = match self.opts.structure {
+            Structure::Auto => ext_specified,
+            Structure::Keep => false,
+            Structure::Flatten => true,
+        };
+        let templated = naming::has_placeholders(&self.p.to);
+        // a missing dir, like a typo in the job, matches nothing
+        if !self.p.from.is_dir() {
+            trace!("no dir to match files in: {:?}", self.p.from);
+            return Ok(paths);
+        }
         let mut dir_walker = WalkDir::new(&self.p.from);
         if only_cur_dir {
             dir_walker = dir_walker.max_depth(1);
         }
-        for file in dir_walker
-            .into_iter()
-            .filter(|f| f.as_ref().unwrap().path().is_file())
-            .filter(|f| fname_filter(f.as_ref().unwrap()))
-        {
-            let file = file.unwrap();
+        for file in dir_walker {
+            let file = file?;
             let src = file.path();
-            let dst: PathBuf;
-            if ext_specified {
-                dst = Path::new(&self.p.to).join(file.file_name())
+            if !src.is_file() || !fname_filter(&file) || self.is_excluded(&excludes, src) {
+                continue;
+            }
+            let mut dst = if flatten {
+                Path::new(&self.p.to).join(file.file_name())
             } else {
-                dst = FileOp::fix_offset(&self.p, src);
+                FileOp::fix_offset(&self.p, src)
+            };
+            if templated {
+                // placeholders are only in the job's destination, not in source names
+                let offset = diff_paths(&dst, &self.p.to).unwrap();
+                dst = naming::expand_dst(&self.p.to, src)?.join(offset);
             }
             paths.push(Paths {
                 from: src.to_owned(),
                 to: dst,
             })
         }
+        if !self.opts.rename.is_empty() {
+            self.rename(&mut paths)?;
+        }
         trace!("{:#?}", paths);
         trace!("{:?}", paths);
-        paths
-    }
-
-    fn file_op(&self, vp: &[Paths]) -> Result<()> {
-        for p in vp {
-            trace!("{:?}", p);
-            let src = Path::new(&p.from);
-            let dst = Path::new(&p.to);
-            assert!(src.exists());
-            assert!(FileOp::is_dst_valid(dst.to_str().unwrap()));
-            if !dst.parent().unwrap().exists() {
-                fs::create_dir_all(dst.parent().unwrap())?;
+        Ok(paths)
+    }
+
+    /// files are renamed in order of their source paths, so "{index}" is the
+    /// same from run to run. two files renamed to the same name are an error.
+    fn rename(&self, paths: &mut [Paths]) -> Result<()> {
+        let renamer = Renamer::new(&self.opts.rename)?;
+        paths.sort();
+        let mut renamed: BTreeMap<PathBuf, &Path> = BTreeMap::new();
+        for (index, p) in paths.iter_mut().enumerate() {
+            p.to = renamer.rename(&p.from, &p.to, index + 1)?;
+            if let Some(other) = renamed.insert(p.to.clone(), &p.from) {
+                bail!(
+                    "{} and {} are both renamed to {}",
+                    other.display(),
+                    p.from.display(),
+                    p.to.display()
+                );
             }
-            if dst.is_file() {
-                trace!("remove file: {:#?}", dst);
-                fs::remove_file(dst)?;
+        }
+        Ok(())
+    }
+
+    fn file_op(&self, vp: &[Paths]) -> Result<Stats> {
+        let (stats, result) = self.file_op_counted(vp);
+        result.map(|()| stats)
+    }
+
+    fn check_opts(&self) -> Result<()> {
+        if self.opts.exec.is_some() && self.op != Some(Operation::Copy_) {
+            bail!("exec only works with copy");
+        }
+        if self.opts.render.is_some() && self.op != Some(Operation::Copy_) {
+            bail!("render only works with copy");
+        }
+        if self.opts.text.is_some() && self.op != Some(Operation::Copy_) {
+            bail!("text filters only work with copy");
+        }
+        Ok(())
+    }
+
+    fn file_op_counted(&self, vp: &[Paths]) -> (Stats, Result<()>) {
+        if let Err(e) = self.check_opts() {
+            return (Stats::default(), Err(e));
+        }
+        let op_start = Instant::now();
+        let mut stats = Stats {
+            matched: vp.len() as u64,
+            ..Default::default()
+        };
+        let sizes: Vec<u64> = vp.iter().map(|p| FileOp::size_of(&p.from)).collect();
+        let op = self.op.unwrap().name();
+        for (p, size) in vp.iter().zip(&sizes) {
+            self.emit(|| Event::FilePlanned {
+                op,
+                src: p.from.clone(),
+                dst: p.to.clone(),
+                size: *size,
+            });
+        }
+        for (p, size) in vp.iter().zip(sizes) {
+            self.progress(|progress| progress.start_file(&p.from));
+            let start = Instant::now();
+            match self.path_op(p) {
+                Ok(Outcome::Transferred { overwritten }) => {
+                    let duration_ms = events::millis(start.elapsed());
+                    match self.op {
+                        Some(Operation::Copy_) => stats.copied += 1,
+                        Some(Operation::Hardlink) => stats.linked += 1,
+                        Some(Operation::Move) => stats.moved += 1,
+                        None => unreachable!(),
+                    }
+                    if overwritten {
+                        stats.overwritten += 1;
+                    }
+                    stats.bytes += size;
+                    stats.add_slow(SlowFile {
+                        src: p.from.clone(),
+                        size,
+                        duration_ms,
+                    });
+                    self.emit(|| Event::FileTransferred {
+                        op,
+                        src: p.from.clone(),
+                        dst: p.to.clone(),
+                        size,
+                        duration_ms,
+                    });
+                }
+                Ok(Outcome::Skipped(reason)) => {
+                    stats.skipped += 1;
+                    self.emit(|| Event::FileSkipped {
+                        op,
+                        src: p.from.clone(),
+                        dst: p.to.clone(),
+                        size,
+                        reason,
+                    });
+                }
+                Err(e) => {
+                    self.emit(|| Event::Error {
+                        op,
+                        src: p.from.clone(),
+                        dst: p.to.clone(),
+                        message: format!("{:#}", e),
+                    });
+                    stats.elapsed_ms = events::millis(op_start.elapsed());
+                    return (stats, Err(e));
+                }
+            }
+            self.progress(|progress| progress.file_done(size));
+        }
+        stats.elapsed_ms = events::millis(op_start.elapsed());
+        (stats, Ok(()))
+    }
+
+    /// size of a file, or of all files in a dir
+    pub(crate) fn size_of(path: &Path) -> u64 {
+        WalkDir::new(path)
+            .into_iter()
+            .filter_map(|f| f.ok())
+            .filter(|f| f.file_type().is_file())
+            .filter_map(|f| f.metadata().ok())
+            .map(|m| m.len())
+            .sum()
+    }
+
+    fn path_op(&self, p: &Paths) -> Result<Outcome> {
+        trace!("{:?}", p);
+        let src = Path::new(&p.from);
+        let dst = Path::new(&p.to);
+        if self.is_done(src) {
+            trace!("done in an earlier run: {:?}", src);
+            return Ok(Outcome::Skipped("done_before"));
+        }
+        if !src.exists() {
+            bail!("source doesn't exist: {}", src.display());
+        }
+        assert!(FileOp::is_dst_valid(dst));
+        if !dst.parent().unwrap().exists() {
+            self.create_dir_all(dst.parent().unwrap())?;
+        }
+        let dst_existed = dst.exists();
+        if dst.is_file() {
+            match self.opts.on_conflict {
+                OnConflict::Overwrite if !self.opts.atomic => {
+                    self.journal(|j| j.backup(dst))?;
+                    trace!("remove file: {:#?}", dst);
+                    fs::remove_file(dst)?;
+                }
+                OnConflict::Overwrite => self.journal(|j| j.backup(dst))?,
+                OnConflict::Skip => {
+                    trace!("skip existing file: {:#?}", dst);
+                    return Ok(Outcome::Skipped("exists"));
+                }
+                OnConflict::Error => {
+                    bail!("destination file already exists: {}", dst.display())
+                }
             }
-            match self.op {
-                Some(Operation::Copy_) => {
-                    let _ = fs::copy(&src, &dst)?;
+        }
+        match self.op {
+            Some(Operation::Copy_) => self.write_dst(dst, |tmp| {
+                // a transformed file doesn't match its source, it isn't verified
+                match &self.opts.exec {
+                    Some(exec) => hooks::run(exec, &[("src", src), ("dst", tmp)])?,
+                    None if self.filter_file(src, tmp)? => {}
+                    None => {
+                        self.copy_file(src, dst, tmp)?;
+                        self.verify_dst(src, tmp)?;
+                    }
+                }
+                if self.opts.preserve_mtime {
+                    FileOp::copy_mtime(src, tmp)?;
+                }
+                Ok(())
+            })?,
+            Some(Operation::Hardlink) => self.write_dst(dst, |tmp| Ok(fs::hard_link(src, tmp)?))?,
+            Some(Operation::Move) => self.move_path(src, dst, &|src, dst| fs::rename(src, dst))?,
+            None => unreachable!(),
+        }
+        match self.op {
+            Some(Operation::Move) => self.journal(|j| j.moved(src, dst))?,
+            _ if !dst_existed => self.journal(|j| j.created(dst))?,
+            _ => {}
+        }
+        if let Some(checkpoint) = &self.checkpoint {
+            checkpoint.file_done(src)?;
+        }
+        Ok(Outcome::Transferred {
+            overwritten: dst_existed,
+        })
+    }
+
+    /// writes `src` to `dst` rendered and normalized, when render or text
+    /// filters apply to it. false when none does, and nothing is written.
+    fn filter_file(&self, src: &Path, dst: &Path) -> Result<bool> {
+        let render = self.opts.render.as_ref().filter(|r| r.renders(src));
+        let text = match &self.opts.text {
+            Some(text) if text::is_text(src, text)? => Some(text),
+            _ => None,
+        };
+        if render.is_none() && text.is_none() {
+            return Ok(false);
+        }
+        let mut contents = fs::read(src)?;
+        if let Some(render) = render {
+            contents = template::render_contents(contents, render)
+                .with_context(|| format!("can't render {}", src.display()))?;
+        }
+        if let Some(text) = text {
+            contents = text::normalize(&contents, text);
+        }
+        fs::write(dst, contents)?;
+        // like a copy, so a filtered script stays executable
+        fs::set_permissions(dst, fs::metadata(src)?.permissions())?;
+        Ok(true)
+    }
+
+    /// rename doesn't work across file systems. then files are copied, and each
+    /// source file is removed only after its copy is complete and verified.
+    /// `rename` is `fs::rename`, tests give one which fails.
+    fn move_path(&self, src: &Path, dst: &Path, rename: &Rename) -> Result<()> {
+        match rename(src, dst) {
+            Err(e) if journal::crosses_devices(&e) => {
+                trace!("{:?} is on another device, copy and remove it", src);
+                if src.is_dir() {
+                    for file in WalkDir::new(src)
+                        .into_iter()
+                        .filter(|f| f.as_ref().unwrap().path().is_file())
+                    {
+                        let file = file.unwrap();
+                        let dst_file = dst.join(diff_paths(file.path(), src).unwrap());
+                        fs::create_dir_all(dst_file.parent().unwrap())?;
+                        self.move_path(file.path(), &dst_file, rename)?;
+                    }
+                    fs::remove_dir_all(src)?;
+                } else {
+                    self.write_dst(dst, |tmp| {
+                        self.copy_file(src, dst, tmp)?;
+                        self.verify_dst(src, tmp)?;
+                        FileOp::copy_mtime(src, tmp)?;
+                        Ok(())
+                    })?;
+                    fs::remove_file(src)?;
                 }
-                Some(Operation::Hardlink) => fs::hard_link(&src, &dst)?,
-                Some(Operation::Move) => fs::rename(&src, &dst)?,
-                None => unreachable!(),
+                Ok(())
+            }
+            result => Ok(result?),
+        }
+    }
+
+    /// `write` creates the destination at the path it's given. when atomic, that's a
+    /// temp file in the destination dir which is renamed over the destination once
+    /// complete, so readers only ever see the old or the new complete file.
+    fn write_dst<F>(&self, dst: &Path, write: F) -> Result<()>
+    where
+        F: FnOnce(&Path) -> Result<()>,
+    {
+        if !self.opts.atomic {
+            return write(dst);
+        }
+        let tmp = FileOp::tmp_path(dst);
+        if let Err(e) = write(&tmp).and_then(|_| Ok(fs::rename(&tmp, dst)?)) {
+            let _ = fs::remove_file(&tmp);
+            return Err(e);
+        }
+        // renaming a hardlink over another link of the same file does nothing,
+        // so the temp name is left
+        if fs::symlink_metadata(&tmp).is_ok() {
+            fs::remove_file(&tmp)?;
+        }
+        Ok(())
+    }
+
+    fn tmp_path(dst: &Path) -> PathBuf {
+        let suffix = format!(".{}.esycpy-tmp", std::process::id());
+        FileOp::sibling_path(dst, ".", &suffix)
+    }
+
+    /// a destination which doesn't match its source is removed
+    fn verify_dst(&self, src: &Path, dst: &Path) -> Result<()> {
+        if self.opts.verify {
+            if let Err(e) = checksum::verify(src, dst, self.opts.hash) {
+                fs::remove_file(dst)?;
+                return Err(e);
             }
         }
         Ok(())
     }
 
-    fn is_dst_valid(dst: &str) -> bool {
-        let mut dst_path = Path::new(dst);
+    fn copy_mtime(src: &Path, dst: &Path) -> Result<()> {
+        let mtime = fs::metadata(src)?.modified()?;
+        fs::OpenOptions::new()
+            .write(true)
+            .open(dst)?
+            .set_modified(mtime)?;
+        Ok(())
+    }
+
+    fn is_dst_valid<P: AsRef<Path>>(dst: P) -> bool {
+        let mut dst_path = dst.as_ref();
         loop {
             if dst_path.exists() {
                 return true;
@@ -201,6 +870,7 @@ impl FileOp {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::job::HashAlgo;
     use tempfile::TempDir;
 
     #[test]
@@ -221,7 +891,7 @@ mod tests {
         let dst_dir = tmp_dir.path().join("dst");
         let dst_file = dst_dir.join("sample_file");
         file_op
-            .file_op(&vec![Paths {
+            .file_op(&[Paths {
                 from: src_file.clone(),
                 to: dst_file.clone(),
             }])
@@ -256,7 +926,7 @@ mod tests {
         let dst_dir = tmp_dir.path().join("dst");
         let dst_file = dst_dir.join("sample_file");
         file_op
-            .file_op(&vec![Paths {
+            .file_op(&[Paths {
                 from: src_file.clone(),
                 to: dst_file.clone(),
             }])
@@ -291,7 +961,7 @@ mod tests {
         let dst_dir = tmp_dir.path().join("dst");
         let dst_file = dst_dir.join("sample_file");
         file_op
-            .file_op(&vec![Paths {
+            .file_op(&[Paths {
                 from: src_file.clone(),
                 to: dst_file.clone(),
             }])
@@ -327,7 +997,7 @@ mod tests {
         let dst_dir = tmp_dir.path().join("dst");
         let dst_file = dst_dir.join("sample_file");
         file_op
-            .file_op(&vec![Paths {
+            .file_op(&[Paths {
                 from: src_file.clone(),
                 to: dst_file.clone(),
             }])
@@ -350,10 +1020,25 @@ mod tests {
 
     #[test]
     fn dst_valid() {
-        assert_eq!(FileOp::is_dst_valid("c:/users/test/invalid_path"), true);
-        assert_eq!(FileOp::is_dst_valid(" c:/users/test/invalid_path"), false);
-        assert_eq!(FileOp::is_dst_valid(" \\Debug\\bin"), false);
-        assert_eq!(FileOp::is_dst_valid("c:\\users\\Debug\\bin"), true);
+        if cfg!(windows) {
+            assert!(FileOp::is_dst_valid("c:/users/test/invalid_path"));
+            assert!(!FileOp::is_dst_valid(" c:/users/test/invalid_path"));
+            assert!(!FileOp::is_dst_valid(" \\Debug\\bin"));
+            assert!(FileOp::is_dst_valid("c:\\users\\Debug\\bin"));
+        } else {
+            assert!(FileOp::is_dst_valid("/users/test/invalid_path"));
+            assert!(!FileOp::is_dst_valid(" users/test/invalid_path"));
+        }
+    }
+
+    #[test]
+    fn dir_path() {
+        assert!(FileOp::is_dir_path(Path::new("./test_files")));
+        assert!(FileOp::is_dir_path(Path::new("not/existing/dir/")));
+        assert!(!FileOp::is_dir_path(Path::new("not/existing/file")));
+        assert!(!FileOp::is_dir_path(Path::new(
+            "./test_files/for_file_operations/sample_file"
+        )));
     }
 
     #[test]
@@ -378,13 +1063,14 @@ mod tests {
         let s_src = PathBuf::from("./test_files/test_src_dst_paths");
         let s_dst = dst_dir;
         trace!("reached here");
-        let file_op = FileOp::from(ArgsType::CmdLine {
-            op: Operation::Move,
-            from: s_src.clone(),
-            to: s_dst.clone(),
-        });
+        let file_op = FileOp::new(
+            Operation::Move,
+            s_src.clone(),
+            s_dst.clone(),
+            JobOptions::default(),
+        );
         trace!("{:?}", file_op);
-        let mut v_returned = file_op.get_src_dst_paths(|_| true, false, false);
+        let mut v_returned = file_op.get_src_dst_paths(|_| true, false, false).unwrap();
         fix_path_vec(&mut v_returned);
         v_returned.sort_unstable();
         let mut v_test: Vec<Paths> = vec![
@@ -416,26 +1102,23 @@ mod tests {
         let dst_dir = tmp_dir.path().join("dst");
         let s_src = PathBuf::from("./test_files/test_src_dst_paths/*.file");
         let s_dst = dst_dir;
-        let file_op = FileOp::from(ArgsType::CmdLine {
-            op: Operation::Move,
-            from: s_src.clone(),
-            to: s_dst.clone(),
-        });
-        trace!("{:?}", file_op);
-        let mut v_returned = file_op.get_src_dst_paths(
-            |f| {
-                let ext = f
-                    .file_name()
-                    .to_str()
-                    .unwrap()
-                    .rsplit(|c| c == '.')
-                    .next()
-                    .unwrap();
-                ext == "file"
-            },
-            true,
-            true,
+        let file_op = FileOp::new(
+            Operation::Move,
+            s_src.clone(),
+            s_dst.clone(),
+            JobOptions::default(),
         );
+        trace!("{:?}", file_op);
+        let mut v_returned = file_op
+            .get_src_dst_paths(
+                |f| {
+                    let ext = f.file_name().to_str().unwrap().rsplit('.').next().unwrap();
+                    ext == "file"
+                },
+                true,
+                true,
+            )
+            .unwrap();
         fix_path_vec(&mut v_returned);
         v_returned.sort_unstable();
         let mut v_test: Vec<Paths> = vec![Paths {
@@ -453,26 +1136,23 @@ mod tests {
         let dst_dir = tmp_dir.path().join("dst");
         let s_src = PathBuf::from("./test_files/test_src_dst_paths/**.file");
         let s_dst = dst_dir;
-        let file_op = FileOp::from(ArgsType::CmdLine {
-            op: Operation::Move,
-            from: s_src.clone(),
-            to: s_dst.clone(),
-        });
-        trace!("{:?}", file_op);
-        let mut v_returned = file_op.get_src_dst_paths(
-            |f| {
-                let ext = f
-                    .file_name()
-                    .to_str()
-                    .unwrap()
-                    .rsplit(|c| c == '.')
-                    .next()
-                    .unwrap();
-                ext == "file"
-            },
-            false,
-            true,
+        let file_op = FileOp::new(
+            Operation::Move,
+            s_src.clone(),
+            s_dst.clone(),
+            JobOptions::default(),
         );
+        trace!("{:?}", file_op);
+        let mut v_returned = file_op
+            .get_src_dst_paths(
+                |f| {
+                    let ext = f.file_name().to_str().unwrap().rsplit('.').next().unwrap();
+                    ext == "file"
+                },
+                false,
+                true,
+            )
+            .unwrap();
         fix_path_vec(&mut v_returned);
         v_returned.sort_unstable();
         let mut v_test: Vec<Paths> = vec![
@@ -501,11 +1181,11 @@ mod tests {
 
     #[test]
     fn get_ext_() {
-        assert_eq!(FileOp::get_ext("*").unwrap(), "");
-        assert_eq!(FileOp::get_ext("*.txt").unwrap(), ".txt");
-        assert_eq!(FileOp::get_ext("**.file").unwrap(), ".file");
-        assert_eq!(FileOp::get_ext("**").unwrap(), "");
-        assert_eq!(FileOp::get_ext("**suffix").unwrap(), "suffix");
+        assert_eq!(FileOp::get_ext("*").unwrap(), b"");
+        assert_eq!(FileOp::get_ext("*.txt").unwrap(), b".txt");
+        assert_eq!(FileOp::get_ext("**.file").unwrap(), b".file");
+        assert_eq!(FileOp::get_ext("**").unwrap(), b"");
+        assert_eq!(FileOp::get_ext("**suffix").unwrap(), b"suffix");
     }
 
     #[test]
@@ -525,11 +1205,12 @@ mod tests {
         assert!(src_file.exists());
         let dst_dir = tmp_dir.path().join("dst");
         let dst_file = dst_dir.join("sample_file");
-        let file_op = FileOp::from(ArgsType::CmdLine {
-            op: Operation::Copy_,
-            from: src_file.clone(),
-            to: dst_file.clone(),
-        });
+        let file_op = FileOp::new(
+            Operation::Copy_,
+            src_file.clone(),
+            dst_file.clone(),
+            JobOptions::default(),
+        );
         file_op.process().unwrap();
         assert!(src_file.exists());
         assert!(dst_file.exists());
@@ -559,16 +1240,44 @@ mod tests {
         assert!(src_file.exists());
         let dst_dir = tmp_dir.path().join("dst");
         let dst_file = dst_dir.join("sample_file");
-        let file_op = FileOp::from(ArgsType::CmdLine {
-            op: Operation::Move,
-            from: src_file.clone(),
-            to: dst_file.clone(),
-        });
+        let file_op = FileOp::new(
+            Operation::Move,
+            src_file.clone(),
+            dst_file.clone(),
+            JobOptions::default(),
+        );
         file_op.process().unwrap();
         assert!(!src_file.exists());
         assert!(dst_file.exists());
     }
 
+    #[test]
+    fn hardlink_file_again() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let src_file = tmp_dir.path().join("a.txt");
+        fs::write(&src_file, "a")?;
+        let dst_dir = tmp_dir.path().join("dst");
+        let dst_file = dst_dir.join("a.txt");
+        let file_op = FileOp::new(
+            Operation::Hardlink,
+            src_file.clone(),
+            dst_file.clone(),
+            JobOptions::default(),
+        );
+        file_op.process()?;
+        file_op.process()?;
+        let names: Vec<OsString> = fs::read_dir(&dst_dir)?
+            .map(|f| f.map(|f| f.file_name()))
+            .collect::<io::Result<_>>()?;
+        assert_eq!(names, [OsString::from("a.txt")]);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            assert_eq!(fs::metadata(&src_file)?.nlink(), 2);
+        }
+        Ok(())
+    }
+
     #[test]
     fn hardlink_file() {
         let tmp_dir = TempDir::new().unwrap();
@@ -583,11 +1292,12 @@ mod tests {
         assert!(src_file.exists());
         let dst_dir = tmp_dir.path().join("dst");
         let dst_file = dst_dir.join("sample_file");
-        let file_op = FileOp::from(ArgsType::CmdLine {
-            op: Operation::Hardlink,
-            from: src_file.clone(),
-            to: dst_file.clone(),
-        });
+        let file_op = FileOp::new(
+            Operation::Hardlink,
+            src_file.clone(),
+            dst_file.clone(),
+            JobOptions::default(),
+        );
         file_op.process().unwrap();
         assert!(src_file.exists());
         assert!(dst_file.exists());
@@ -605,19 +1315,22 @@ mod tests {
         fs::create_dir_all(src.clone()).unwrap();
         fs_extra::dir::copy(base, &src, &fs_extra::dir::CopyOptions::default()).unwrap();
         let s_dst = dst_dir;
-        let file_op = FileOp::from(ArgsType::CmdLine {
-            op: Operation::Copy_,
-            from: src.clone(),
-            to: s_dst.clone(),
-        });
+        let file_op = FileOp::new(
+            Operation::Copy_,
+            src.clone(),
+            s_dst.clone(),
+            JobOptions::default(),
+        );
         file_op.process().unwrap();
         let v_src: Vec<String> = WalkDir::new(src)
             .into_iter()
             .map(|f| f.unwrap().path().to_str().unwrap().to_owned())
             .collect();
-        let _v_dst = WalkDir::new(s_dst).into_iter().for_each(|f| {
+        WalkDir::new(s_dst).into_iter().for_each(|f| {
             let dst = f.unwrap().path().to_str().unwrap().to_owned();
-            assert!(v_src.iter().any(|s| s == &dst.replace("\\dst", "\\src")));
+            assert!(v_src
+                .iter()
+                .any(|s| s == &dst.replace(&sep_join("dst"), &sep_join("src"))));
         });
     }
 
@@ -630,20 +1343,23 @@ mod tests {
         fs::create_dir_all(src.clone()).unwrap();
         fs_extra::dir::copy(base, &src, &fs_extra::dir::CopyOptions::default()).unwrap();
         let s_dst = dst_dir;
-        let file_op = FileOp::from(ArgsType::CmdLine {
-            op: Operation::Move,
-            from: src.clone(),
-            to: s_dst.clone(),
-        });
+        let file_op = FileOp::new(
+            Operation::Move,
+            src.clone(),
+            s_dst.clone(),
+            JobOptions::default(),
+        );
         let v_src: Vec<String> = WalkDir::new(&src)
             .into_iter()
             .map(|f| f.unwrap().path().to_str().unwrap().to_owned())
             .collect();
         file_op.process().unwrap();
-        let _v_dst = WalkDir::new(s_dst).into_iter().for_each(|f| {
+        WalkDir::new(s_dst).into_iter().for_each(|f| {
             let dst = f.unwrap().path().to_str().unwrap().to_owned();
             trace!("{}", dst);
-            assert!(v_src.iter().any(|s| s == &dst.replace("\\dst", "\\src")));
+            assert!(v_src
+                .iter()
+                .any(|s| s == &dst.replace(&sep_join("dst"), &sep_join("src"))));
         });
         assert!(!src.exists());
     }
@@ -657,19 +1373,22 @@ mod tests {
         fs::create_dir_all(src.clone()).unwrap();
         fs_extra::dir::copy(base, &src, &fs_extra::dir::CopyOptions::default()).unwrap();
         let s_dst = dst_dir;
-        let file_op = FileOp::from(ArgsType::CmdLine {
-            op: Operation::Hardlink,
-            from: src.clone(),
-            to: s_dst.clone(),
-        });
+        let file_op = FileOp::new(
+            Operation::Hardlink,
+            src.clone(),
+            s_dst.clone(),
+            JobOptions::default(),
+        );
         file_op.process().unwrap();
         let v_src: Vec<String> = WalkDir::new(src)
             .into_iter()
             .map(|f| f.unwrap().path().to_str().unwrap().to_owned())
             .collect();
-        let _v_dst = WalkDir::new(s_dst).into_iter().for_each(|f| {
+        WalkDir::new(s_dst).into_iter().for_each(|f| {
             let dst = f.unwrap().path().to_str().unwrap().to_owned();
-            assert!(v_src.iter().any(|s| s == &dst.replace("\\dst", "\\src")));
+            assert!(v_src
+                .iter()
+                .any(|s| s == &dst.replace(&sep_join("dst"), &sep_join("src"))));
         });
     }
 
@@ -682,11 +1401,12 @@ mod tests {
         fs::create_dir_all(src.clone())?;
         fs_extra::dir::copy(base, &src, &fs_extra::dir::CopyOptions::default())?;
         let s_dst = dst_dir;
-        let file_op = FileOp::from(ArgsType::CmdLine {
-            op: Operation::Copy_,
-            from: Path::new(&src).join("test_src_dst_paths").join("*.file"),
-            to: s_dst.clone(),
-        });
+        let file_op = FileOp::new(
+            Operation::Copy_,
+            Path::new(&src).join("test_src_dst_paths").join("*.file"),
+            s_dst.clone(),
+            JobOptions::default(),
+        );
         file_op.process()?;
         assert!(Path::new(&s_dst).join("f1.file").exists());
         assert!(!Path::new(&s_dst)
@@ -706,11 +1426,12 @@ mod tests {
         fs::create_dir_all(src.clone())?;
         fs_extra::dir::copy(base, &src, &fs_extra::dir::CopyOptions::default())?;
         let s_dst = dst_dir;
-        let file_op = FileOp::from(ArgsType::CmdLine {
-            op: Operation::Copy_,
-            from: Path::new(&src).join("test_src_dst_paths").join("**.file"),
-            to: s_dst.clone(),
-        });
+        let file_op = FileOp::new(
+            Operation::Copy_,
+            Path::new(&src).join("test_src_dst_paths").join("**.file"),
+            s_dst.clone(),
+            JobOptions::default(),
+        );
         file_op.process()?;
         assert!(Path::new(&s_dst).join("f1.file").exists());
         assert!(Path::new(&s_dst).join("f12.file").exists());
@@ -728,11 +1449,12 @@ mod tests {
         fs::create_dir_all(src.clone())?;
         fs_extra::dir::copy(base, &src, &fs_extra::dir::CopyOptions::default())?;
         let s_dst = dst_dir;
-        let file_op = FileOp::from(ArgsType::CmdLine {
-            op: Operation::Copy_,
-            from: Path::new(&src).join("test_src_dst_paths").join("**.img"),
-            to: s_dst.clone(),
-        });
+        let file_op = FileOp::new(
+            Operation::Copy_,
+            Path::new(&src).join("test_src_dst_paths").join("**.img"),
+            s_dst.clone(),
+            JobOptions::default(),
+        );
         file_op.process()?;
         assert!(!Path::new(&s_dst).join("f1.file").exists());
         assert!(!Path::new(&s_dst).join("f12.file").exists());
@@ -750,11 +1472,12 @@ mod tests {
         fs::create_dir_all(src.clone())?;
         fs_extra::dir::copy(base, &src, &fs_extra::dir::CopyOptions::default())?;
         let s_dst = dst_dir;
-        let file_op = FileOp::from(ArgsType::CmdLine {
-            op: Operation::Copy_,
-            from: Path::new(&src).join("test_src_dst_paths").join("**"),
-            to: s_dst.clone(),
-        });
+        let file_op = FileOp::new(
+            Operation::Copy_,
+            Path::new(&src).join("test_src_dst_paths").join("**"),
+            s_dst.clone(),
+            JobOptions::default(),
+        );
         file_op.process()?;
         assert!(Path::new(&s_dst).join("f1.file").exists());
         assert!(Path::new(&s_dst)
@@ -767,13 +1490,554 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn move_dir_with_excludes() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let s_dst = tmp_dir.path().join("dst");
+        let src = tmp_dir.path().join("src");
+        fs::create_dir_all(src.clone())?;
+        let base = Path::new("./test_files/test_src_dst_paths");
+        fs_extra::dir::copy(base, &src, &fs_extra::dir::CopyOptions::default())?;
+        let file_op = FileOp::new(
+            Operation::Move,
+            Path::new(&src).join("test_src_dst_paths"),
+            s_dst.clone(),
+            JobOptions {
+                exclude: vec!["d12".to_owned(), "*.img".to_owned()],
+                ..Default::default()
+            },
+        );
+        file_op.process()?;
+        assert!(Path::new(&s_dst).join("f1.file").exists());
+        assert!(Path::new(&s_dst).join("d1").join("f11.file").exists());
+        assert!(!Path::new(&s_dst).join("d1").join("d12").exists());
+        assert!(!Path::new(&s_dst).join("d3").join("f3.img").exists());
+        assert!(src
+            .join("test_src_dst_paths")
+            .join("d3")
+            .join("f3.img")
+            .exists());
+        Ok(())
+    }
+
+    #[test]
+    fn copy_dir_flatten() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let s_dst = tmp_dir.path().join("dst");
+        let file_op = FileOp::new(
+            Operation::Copy_,
+            PathBuf::from("./test_files/test_src_dst_paths"),
+            s_dst.clone(),
+            JobOptions {
+                structure: Structure::Flatten,
+                ..Default::default()
+            },
+        );
+        file_op.process()?;
+        assert!(Path::new(&s_dst).join("f1.file").exists());
+        assert!(Path::new(&s_dst).join("f11.file").exists());
+        assert!(Path::new(&s_dst).join("f12.file").exists());
+        assert!(Path::new(&s_dst).join("f3.img").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn copy_ext_filter_keep_structure() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let s_dst = tmp_dir.path().join("dst");
+        let file_op = FileOp::new(
+            Operation::Copy_,
+            PathBuf::from("./test_files/test_src_dst_paths/**.file"),
+            s_dst.clone(),
+            JobOptions {
+                structure: Structure::Keep,
+                ..Default::default()
+            },
+        );
+        file_op.process()?;
+        assert!(Path::new(&s_dst).join("f1.file").exists());
+        assert!(Path::new(&s_dst)
+            .join("d1")
+            .join("d12")
+            .join("f12.file")
+            .exists());
+        assert!(!Path::new(&s_dst).join("f12.file").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn copy_file_on_conflict() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let src_file = tmp_dir.path().join("src_file");
+        let dst_file = tmp_dir.path().join("dst_file");
+        fs::write(&src_file, "new")?;
+        fs::write(&dst_file, "old")?;
+        let copy_with = |on_conflict| {
+            FileOp::new(
+                Operation::Copy_,
+                src_file.clone(),
+                dst_file.clone(),
+                JobOptions {
+                    on_conflict,
+                    ..Default::default()
+                },
+            )
+            .process()
+        };
+        copy_with(OnConflict::Skip)?;
+        assert_eq!(fs::read_to_string(&dst_file)?, "old");
+        assert!(copy_with(OnConflict::Error).is_err());
+        assert_eq!(fs::read_to_string(&dst_file)?, "old");
+        copy_with(OnConflict::Overwrite)?;
+        assert_eq!(fs::read_to_string(&dst_file)?, "new");
+        Ok(())
+    }
+
+    #[test]
+    fn copy_file_preserve_mtime() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let src_file = tmp_dir.path().join("src_file");
+        let dst_file = tmp_dir.path().join("dst_file");
+        fs::write(&src_file, "text")?;
+        let mtime = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+        fs::OpenOptions::new()
+            .write(true)
+            .open(&src_file)?
+            .set_modified(mtime)?;
+        let file_op = FileOp::new(
+            Operation::Copy_,
+            src_file,
+            dst_file.clone(),
+            JobOptions {
+                preserve_mtime: true,
+                ..Default::default()
+            },
+        );
+        file_op.process()?;
+        assert_eq!(fs::metadata(dst_file)?.modified()?, mtime);
+        Ok(())
+    }
+
+    #[test]
+    fn copy_multiple_sources() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let s_dst = tmp_dir.path().join("dst");
+        let base = PathBuf::from("./test_files/test_src_dst_paths");
+        let file_ops = FileOp::from(Job {
+            op: Operation::Copy_,
+            from: vec![
+                base.join("f1.file"),
+                base.join("d1"),
+                base.join("d3").join("*.img"),
+            ],
+            to: s_dst.clone(),
+            opts: JobOptions::default(),
+        });
+        assert_eq!(file_ops.len(), 3);
+        for file_op in file_ops {
+            file_op.process()?;
+        }
+        assert!(Path::new(&s_dst).join("f1.file").is_file());
+        assert!(Path::new(&s_dst).join("d1").join("f11.file").exists());
+        assert!(Path::new(&s_dst)
+            .join("d1")
+            .join("d12")
+            .join("f12.file")
+            .exists());
+        assert!(Path::new(&s_dst).join("f3.img").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn copy_and_move_verified() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let src_file = tmp_dir.path().join("src_file");
+        fs::write(&src_file, "text")?;
+        let opts = JobOptions {
+            verify: true,
+            hash: HashAlgo::Sha256,
+            ..Default::default()
+        };
+        let copied = tmp_dir.path().join("copied");
+        FileOp::new(
+            Operation::Copy_,
+            src_file.clone(),
+            copied.clone(),
+            opts.clone(),
+        )
+        .process()?;
+        assert_eq!(fs::read_to_string(&copied)?, "text");
+        let moved = tmp_dir.path().join("moved");
+        FileOp::new(Operation::Move, src_file.clone(), moved.clone(), opts).process()?;
+        assert_eq!(fs::read_to_string(&moved)?, "text");
+        assert!(!src_file.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn overwrite_atomically() -> Result<()> {
+        for atomic in &[true, false] {
+            for op in &[Operation::Copy_, Operation::Hardlink] {
+                let tmp_dir = TempDir::new()?;
+                let src_file = tmp_dir.path().join("src_file");
+                let dst_file = tmp_dir.path().join("dst").join("dst_file");
+                fs::write(&src_file, "new")?;
+                fs::create_dir_all(dst_file.parent().unwrap())?;
+                fs::write(&dst_file, "old")?;
+                let opts = JobOptions {
+                    atomic: *atomic,
+                    ..Default::default()
+                };
+                FileOp::new(*op, src_file, dst_file.clone(), opts).process()?;
+                assert_eq!(fs::read_to_string(&dst_file)?, "new");
+                assert_eq!(fs::read_dir(dst_file.parent().unwrap())?.count(), 1);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn failed_atomic_write_keeps_dst() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let dst_file = tmp_dir.path().join("dst_file");
+        fs::write(&dst_file, "old")?;
+        let file_op = FileOp::default();
+        let result = file_op.write_dst(&dst_file, |tmp| {
+            fs::write(tmp, "half writ")?;
+            bail!("interrupted")
+        });
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&dst_file)?, "old");
+        assert_eq!(fs::read_dir(tmp_dir.path())?.count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn journaled_ops_undone() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let root = tmp_dir.path().join("journal");
+        let src = tmp_dir.path().join("src");
+        fs::create_dir_all(src.join("sub"))?;
+        fs::write(src.join("a.txt"), "new a")?;
+        fs::write(src.join("sub").join("b.txt"), "b")?;
+        let dst = tmp_dir.path().join("dst");
+        fs::create_dir_all(&dst)?;
+        fs::write(dst.join("a.txt"), "old a")?;
+        let journal = Rc::new(Journal::new(&root));
+        let mut copy_op = FileOp::new(
+            Operation::Copy_,
+            src.clone(),
+            dst.clone(),
+            JobOptions::default(),
+        );
+        copy_op.set_journal(journal.clone());
+        copy_op.process()?;
+        let moved = tmp_dir.path().join("moved");
+        let mut move_op = FileOp::new(
+            Operation::Move,
+            src.clone(),
+            moved.clone(),
+            Default::default(),
+        );
+        move_op.set_journal(journal.clone());
+        move_op.process()?;
+        assert_eq!(fs::read_to_string(dst.join("a.txt"))?, "new a");
+        assert!(!src.exists());
+
+        crate::journal::undo(&root, None)?;
+        assert_eq!(fs::read_to_string(src.join("sub").join("b.txt"))?, "b");
+        assert!(!moved.exists());
+        assert_eq!(fs::read_to_string(dst.join("a.txt"))?, "old a");
+        assert!(!dst.join("sub").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn process_stats() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let src = PathBuf::from("./test_files/test_src_dst_paths/**.file");
+        let dst = tmp_dir.path().join("dst");
+        let opts = JobOptions {
+            on_conflict: OnConflict::Skip,
+            ..Default::default()
+        };
+        let stats =
+            FileOp::new(Operation::Copy_, src.clone(), dst.clone(), opts.clone()).process()?;
+        assert_eq!(stats.matched, 3);
+        assert_eq!(stats.copied, 3);
+        assert_eq!(stats.skipped, 0);
+        fs::remove_file(dst.join("f1.file"))?;
+        let stats = FileOp::new(Operation::Copy_, src, dst, opts).process()?;
+        assert_eq!(stats.copied, 1);
+        assert_eq!(stats.skipped, 2);
+        assert_eq!(stats.overwritten, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn nothing_matched() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let src = PathBuf::from("./test_files/test_src_dst_paths/**.nothing");
+        let process = |on_empty| {
+            let opts = JobOptions {
+                on_empty,
+                ..Default::default()
+            };
+            FileOp::new(
+                Operation::Copy_,
+                src.clone(),
+                tmp_dir.path().to_owned(),
+                opts,
+            )
+            .process()
+        };
+        assert_eq!(process(OnEmpty::Ok)?.matched, 0);
+        assert_eq!(process(OnEmpty::Warn)?.matched, 0);
+        let err = process(OnEmpty::Error).unwrap_err();
+        assert!(err.to_string().contains("no files matched"));
+        assert!(err.to_string().contains("**.nothing"));
+        Ok(())
+    }
+
+    #[test]
+    fn missing_dir_matched_nothing() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let src = tmp_dir.path().join("asets").join("*.xml");
+        let file_op = |on_empty| {
+            let opts = JobOptions {
+                on_empty,
+                ..Default::default()
+            };
+            FileOp::new(
+                Operation::Copy_,
+                src.clone(),
+                tmp_dir.path().join("out"),
+                opts,
+            )
+        };
+        assert!(file_op(OnEmpty::Warn).plan()?.is_empty());
+        assert_eq!(file_op(OnEmpty::Warn).process()?.matched, 0);
+        let err = file_op(OnEmpty::Error).process().unwrap_err();
+        assert!(err.to_string().contains("no files matched"));
+        let job = Job::copy(&src, tmp_dir.path().join("out"));
+        assert!(job.plan()?.is_empty());
+        job.inputs()?;
+        Ok(())
+    }
+
+    #[test]
+    fn missing_file_matched_nothing() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let src = tmp_dir.path().join("missing.txt");
+        let process = |on_empty| {
+            let opts = JobOptions {
+                on_empty,
+                ..Default::default()
+            };
+            let dst = tmp_dir.path().join("dst.txt");
+            FileOp::new(Operation::Copy_, src.clone(), dst, opts).process()
+        };
+        assert_eq!(process(OnEmpty::Warn)?.matched, 0);
+        assert!(!tmp_dir.path().join("dst.txt").exists());
+        let err = process(OnEmpty::Error).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("no files matched {}", src.display())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn verify_mismatch_removes_dst() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let src_file = tmp_dir.path().join("src_file");
+        let dst_file = tmp_dir.path().join("dst_file");
+        fs::write(&src_file, "text")?;
+        fs::write(&dst_file, "tex")?;
+        let file_op = FileOp {
+            opts: JobOptions {
+                verify: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(file_op.verify_dst(&src_file, &dst_file).is_err());
+        assert!(src_file.exists());
+        assert!(!dst_file.exists());
+        Ok(())
+    }
+
+    /// a rename which fails like one to another device
+    fn rename_across(_: &Path, _: &Path) -> io::Result<()> {
+        let crosses_devices = if cfg!(windows) { 17 } else { 18 };
+        Err(io::Error::from_raw_os_error(crosses_devices))
+    }
+
+    #[test]
+    fn move_across_devices() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let src = tmp_dir.path().join("src");
+        fs::create_dir_all(src.join("sub"))?;
+        fs::write(src.join("a.txt"), "a")?;
+        fs::write(src.join("sub").join("b.txt"), "b")?;
+        let dst = tmp_dir.path().join("dst");
+        let file_op = FileOp {
+            opts: JobOptions {
+                verify: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        file_op.move_path(&src, &dst, &rename_across)?;
+        assert_eq!(fs::read_to_string(dst.join("a.txt"))?, "a");
+        assert_eq!(fs::read_to_string(dst.join("sub").join("b.txt"))?, "b");
+        assert!(!src.exists());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn move_across_devices_verify_mismatch_keeps_src() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let src_file = tmp_dir.path().join("src_file");
+        let dst_file = tmp_dir.path().join("dst_file");
+        fs::write(&src_file, "text")?;
+        let file_op = FileOp {
+            opts: JobOptions {
+                verify: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        // the copy goes to /dev/null, so it doesn't match its source
+        let tmp = FileOp::tmp_path(&dst_file);
+        std::os::unix::fs::symlink("/dev/null", &tmp)?;
+        let e = file_op
+            .move_path(&src_file, &dst_file, &rename_across)
+            .unwrap_err();
+        assert!(e.to_string().starts_with("checksum mismatch"));
+        assert_eq!(fs::read_to_string(&src_file)?, "text");
+        assert!(!dst_file.exists());
+        assert!(fs::symlink_metadata(&tmp).is_err());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn filtered_file_keeps_permissions() -> Result<()> {
+        use crate::job::{Eol, Render, TextFilter};
+        use std::os::unix::fs::PermissionsExt;
+        let tmp_dir = TempDir::new()?;
+        let src = tmp_dir.path().join("src");
+        fs::create_dir_all(&src)?;
+        fs::write(src.join("run.sh"), "#!/bin/sh\r\necho hi\r\n")?;
+        fs::set_permissions(src.join("run.sh"), fs::Permissions::from_mode(0o755))?;
+        fs::write(src.join("setup.txt"), "out={OutDir}")?;
+        fs::set_permissions(src.join("setup.txt"), fs::Permissions::from_mode(0o640))?;
+        let dst = tmp_dir.path().join("dst");
+        let opts = JobOptions {
+            render: Some(Render {
+                variables: vec![("OutDir".to_owned(), "bin".to_owned())],
+                ..Default::default()
+            }),
+            text: Some(TextFilter {
+                eol: Some(Eol::Lf),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        FileOp::new(Operation::Copy_, src, dst.clone(), opts).process()?;
+        assert_eq!(
+            fs::read_to_string(dst.join("run.sh"))?,
+            "#!/bin/sh\necho hi\n"
+        );
+        assert_eq!(fs::read_to_string(dst.join("setup.txt"))?, "out=bin");
+        let mode = |name| -> Result<u32> {
+            Ok(fs::metadata(dst.join(name))?.permissions().mode() & 0o777)
+        };
+        assert_eq!(mode("run.sh")?, 0o755);
+        assert_eq!(mode("setup.txt")?, 0o640);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    fn non_utf8_name(name: &[u8]) -> &OsStr {
+        use std::os::unix::ffi::OsStrExt;
+        OsStr::from_bytes(name)
+    }
+
+    #[cfg(unix)]
+    fn non_utf8_src(root: &Path) -> Result<PathBuf> {
+        let src = root.join("src");
+        let dir = src.join(non_utf8_name(b"d\xff"));
+        fs::create_dir_all(&dir)?;
+        fs::write(src.join(non_utf8_name(b"f\xfe.ext")), "f")?;
+        fs::write(dir.join(non_utf8_name(b"g\xfd.ext")), "g")?;
+        fs::write(dir.join(non_utf8_name(b"h\xfc.tmp")), "h")?;
+        Ok(src)
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn non_utf8_names() -> Result<()> {
+        for op in &[Operation::Copy_, Operation::Move, Operation::Hardlink] {
+            let tmp_dir = TempDir::new()?;
+            let src = non_utf8_src(tmp_dir.path())?;
+            let dst = tmp_dir.path().join("dst_file");
+            let src_file = src.join(non_utf8_name(b"f\xfe.ext"));
+            FileOp::new(*op, src_file, dst.clone(), JobOptions::default()).process()?;
+            assert_eq!(fs::read_to_string(&dst)?, "f");
+
+            let src = non_utf8_src(&tmp_dir.path().join("dir"))?;
+            let dst = tmp_dir.path().join("dst_dir");
+            FileOp::new(*op, src.clone(), dst.clone(), JobOptions::default()).process()?;
+            assert!(dst.join(non_utf8_name(b"f\xfe.ext")).exists());
+            assert!(dst
+                .join(non_utf8_name(b"d\xff"))
+                .join(non_utf8_name(b"g\xfd.ext"))
+                .exists());
+
+            let src = non_utf8_src(&tmp_dir.path().join("filter"))?;
+            let dst = tmp_dir.path().join("dst_filter");
+            let filter = src.join("**.ext");
+            FileOp::new(*op, filter, dst.clone(), JobOptions::default()).process()?;
+            assert!(dst.join(non_utf8_name(b"f\xfe.ext")).exists());
+            assert!(dst.join(non_utf8_name(b"g\xfd.ext")).exists());
+            assert!(!dst.join(non_utf8_name(b"h\xfc.tmp")).exists());
+
+            let src = non_utf8_src(&tmp_dir.path().join("pattern"))?;
+            let dst = tmp_dir.path().join("dst_pattern");
+            let filter = src.join(non_utf8_name(b"**\xfd.ext"));
+            FileOp::new(*op, filter, dst.clone(), JobOptions::default()).process()?;
+            assert!(!dst.join(non_utf8_name(b"f\xfe.ext")).exists());
+            assert!(dst.join(non_utf8_name(b"g\xfd.ext")).exists());
+
+            let src = non_utf8_src(&tmp_dir.path().join("exclude"))?;
+            let dst = tmp_dir.path().join("dst_exclude");
+            let opts = JobOptions {
+                exclude: vec!["*.tmp".to_owned(), "f*".to_owned()],
+                ..Default::default()
+            };
+            FileOp::new(*op, src, dst.clone(), opts).process()?;
+            let dst_d = dst.join(non_utf8_name(b"d\xff"));
+            assert!(!dst.join(non_utf8_name(b"f\xfe.ext")).exists());
+            assert!(dst_d.join(non_utf8_name(b"g\xfd.ext")).exists());
+            assert!(!dst_d.join(non_utf8_name(b"h\xfc.tmp")).exists());
+        }
+        Ok(())
+    }
+
+    fn sep_join(dir: &str) -> String {
+        format!("{}{}", std::path::MAIN_SEPARATOR, dir)
+    }
+
     fn fix_path(input: &str) -> String {
         let forward_slash = input.replace("\\", "/");
         trace!("{}", forward_slash.clone());
         let mut only_one_slash = String::new();
         let mut prev_char: Option<char> = None;
         forward_slash.chars().for_each(|c| {
-            only_one_slash.push(c.clone());
+            only_one_slash.push(c);
             if c == '/' && prev_char == Some('/') {
                 only_one_slash.pop();
             }
@@ -0,0 +1,233 @@
+use log::info;
+use std::cell::{Cell, RefCell};
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// how often the progress line is redrawn on a terminal
+const DRAW_INTERVAL: Duration = Duration::from_millis(100);
+/// how often progress is logged, when stderr isn't a terminal
+const LOG_INTERVAL: Duration = Duration::from_secs(5);
+/// bytes of a file copied between updates of the progress
+const COPY_CHUNK: usize = 1024 * 1024;
+
+/// files and bytes of the run. totals are known before the first file is
+/// transferred, except of jobs with a "before" hook, which can make their files.
+#[derive(Debug)]
+pub struct Progress {
+    tty: bool,
+    start: Instant,
+    last_shown: Cell<Option<Instant>>,
+    files_total: Cell<u64>,
+    bytes_total: Cell<u64>,
+    files_done: Cell<u64>,
+    bytes_done: Cell<u64>,
+    /// bytes of the current file in `bytes_done`
+    file_bytes: Cell<u64>,
+    current: RefCell<PathBuf>,
+}
+
+impl Progress {
+    /// drawn on stderr when it's a terminal and not `quiet`, logged otherwise
+    pub fn new(quiet: bool) -> Self {
+        Self {
+            tty: !quiet && io::stderr().is_terminal(),
+            start: Instant::now(),
+            last_shown: Cell::new(None),
+            files_total: Cell::new(0),
+            bytes_total: Cell::new(0),
+            files_done: Cell::new(0),
+            bytes_done: Cell::new(0),
+            file_bytes: Cell::new(0),
+            current: RefCell::new(PathBuf::new()),
+        }
+    }
+
+    pub fn planned(&self, files: u64, bytes: u64) {
+        self.files_total.set(self.files_total.get() + files);
+        self.bytes_total.set(self.bytes_total.get() + bytes);
+    }
+
+    pub fn start_file(&self, path: &Path) {
+        *self.current.borrow_mut() = path.to_owned();
+        self.file_bytes.set(0);
+        self.show(false);
+    }
+
+    /// `bytes` more of the current file are transferred
+    pub fn advanced(&self, bytes: u64) {
+        self.file_bytes.set(self.file_bytes.get() + bytes);
+        self.bytes_done.set(self.bytes_done.get() + bytes);
+        self.show(false);
+    }
+
+    /// `bytes` is the size of the whole file, what isn't shown yet is added
+    pub fn file_done(&self, bytes: u64) {
+        self.files_done.set(self.files_done.get() + 1);
+        let left = bytes.saturating_sub(self.file_bytes.replace(0));
+        self.bytes_done.set(self.bytes_done.get() + left);
+        self.show(false);
+    }
+
+    /// copies `reader` to `writer` in chunks, the bytes are shown as they're written
+    pub fn copy<R: Read, W: Write>(&self, reader: &mut R, writer: &mut W) -> io::Result<u64> {
+        let mut buf = vec![0; COPY_CHUNK];
+        let mut copied = 0;
+        loop {
+            let n = match reader.read(&mut buf) {
+                Ok(0) => return Ok(copied),
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            writer.write_all(&buf[..n])?;
+            copied += n as u64;
+            self.advanced(n as u64);
+        }
+    }
+
+    fn show(&self, force: bool) {
+        let interval = if self.tty {
+            DRAW_INTERVAL
+        } else {
+            LOG_INTERVAL
+        };
+        let now = Instant::now();
+        // short runs show nothing, not even their final state
+        let due = match self.last_shown.get() {
+            Some(last) => force || now - last >= interval,
+            None => now - self.start >= interval,
+        };
+        if !due {
+            return;
+        }
+        self.last_shown.set(Some(now));
+        let line = self.line(now - self.start);
+        if self.tty {
+            // "\x1b[K" clears what's left of a longer earlier line
+            eprint!("\r{}\x1b[K", line);
+            let _ = io::stderr().flush();
+        } else {
+            info!("{}", line);
+        }
+    }
+
+    fn line(&self, elapsed: Duration) -> String {
+        let bytes_done = self.bytes_done.get();
+        let bytes_total = self.bytes_total.get();
+        let rate = bytes_done as f64 / elapsed.as_secs_f64().max(0.001);
+        let eta = if rate > 0.0 {
+            let secs = bytes_total.saturating_sub(bytes_done) as f64 / rate;
+            format_duration(Duration::from_secs_f64(secs))
+        } else {
+            String::from("-")
+        };
+        let mut line = format!(
+            "{}/{} files, {}/{}, {}/s, ETA {}",
+            self.files_done.get(),
+            self.files_total.get(),
+            format_bytes(bytes_done),
+            format_bytes(bytes_total),
+            format_bytes(rate as u64),
+            eta
+        );
+        let current = self.current.borrow();
+        if let Some(name) = current.file_name() {
+            line.push_str(&format!(", {}", name.to_string_lossy()));
+        }
+        line
+    }
+}
+
+impl Default for Progress {
+    fn default() -> Self {
+        Self::new(false)
+    }
+}
+
+/// shows the final state, and ends the progress line
+impl Drop for Progress {
+    fn drop(&mut self) {
+        self.current.borrow_mut().clear();
+        self.show(true);
+        if self.tty && self.last_shown.get().is_some() {
+            eprintln!();
+        }
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 3600 {
+        format!("{}h{:02}m", secs / 3600, secs % 3600 / 60)
+    } else if secs >= 60 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formatting() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(3 * 1024 * 1024 * 1024), "3.0 GiB");
+        assert_eq!(format_duration(Duration::from_secs(42)), "42s");
+        assert_eq!(format_duration(Duration::from_secs(65)), "1m05s");
+        assert_eq!(format_duration(Duration::from_secs(7320)), "2h02m");
+    }
+
+    #[test]
+    fn progress_line() {
+        let progress = Progress::default();
+        progress.planned(4, 4096);
+        progress.planned(1, 4096);
+        progress.start_file(Path::new("dir/file.ogg"));
+        progress.file_done(2048);
+        assert_eq!(
+            progress.line(Duration::from_secs(2)),
+            "1/5 files, 2.0 KiB/8.0 KiB, 1.0 KiB/s, ETA 6s, file.ogg"
+        );
+    }
+
+    #[test]
+    fn bytes_shown_while_copying() -> io::Result<()> {
+        let progress = Progress::default();
+        progress.planned(2, 3 * COPY_CHUNK as u64);
+        progress.start_file(Path::new("big.bin"));
+        let src = vec![7; 2 * COPY_CHUNK + 10];
+        let mut dst = Vec::new();
+        assert_eq!(
+            progress.copy(&mut src.as_slice(), &mut dst)?,
+            src.len() as u64
+        );
+        assert_eq!(dst, src);
+        assert_eq!(progress.bytes_done.get(), src.len() as u64);
+        assert_eq!(progress.files_done.get(), 0);
+        progress.file_done(src.len() as u64);
+        assert_eq!(progress.bytes_done.get(), src.len() as u64);
+        progress.start_file(Path::new("small.bin"));
+        progress.file_done(100);
+        assert_eq!(progress.bytes_done.get(), src.len() as u64 + 100);
+        Ok(())
+    }
+}
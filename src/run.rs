@@ -0,0 +1,373 @@
+use crate::checkpoint::Checkpoint;
+use crate::events::{self, Event, Events};
+use crate::hooks;
+use crate::job::Job;
+use crate::journal::Journal;
+use crate::operations::FileOp;
+use crate::progress::Progress;
+use crate::stats::{JobReport, Report, Stats};
+use anyhow::Result;
+use log::trace;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::Instant;
+
+/// runs jobs with state shared by all of them. nothing is shared by default:
+///
+/// ```no_run
+/// use esycpy::{Job, Run};
+/// use esycpy::journal::Journal;
+///
+/// let mut run = Run::new().journal(Journal::new("journal".as_ref()));
+/// run.jobs(vec![Job::copy("a", "b"), Job::move_("c", "d/")])?;
+/// println!("{:?}", run.report().total);
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Debug, Default)]
+pub struct Run {
+    journal: Option<Rc<Journal>>,
+    checkpoint: Option<Rc<Checkpoint>>,
+    progress: Option<Rc<Progress>>,
+    events: Option<Rc<Events>>,
+    report: Report,
+}
+
+impl Run {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// changes are recorded, so the run can be undone
+    pub fn journal(mut self, journal: Journal) -> Self {
+        self.journal = Some(Rc::new(journal));
+        self
+    }
+
+    /// jobs and files done in the checkpoint are skipped, it's removed when all
+    /// jobs are done
+    pub fn checkpoint(mut self, checkpoint: Checkpoint) -> Self {
+        self.checkpoint = Some(Rc::new(checkpoint));
+        self
+    }
+
+    pub fn progress(mut self, progress: Progress) -> Self {
+        self.progress = Some(Rc::new(progress));
+        self
+    }
+
+    pub fn events(mut self, events: Events) -> Self {
+        self.events = Some(Rc::new(events));
+        self
+    }
+
+    /// stats of every job run so far, and their total
+    pub fn report(&self) -> &Report {
+        &self.report
+    }
+
+    /// runs jobs in order, the first failed job stops the run
+    pub fn jobs(&mut self, jobs: Vec<Job>) -> Result<()> {
+        let start = Instant::now();
+        let result = self.run_jobs(jobs);
+        self.report.total.elapsed_ms = events::millis(start.elapsed());
+        if let Some(events) = &self.events {
+            events.summary(&self.report.total);
+        }
+        result
+    }
+
+    fn run_jobs(&mut self, jobs: Vec<Job>) -> Result<()> {
+        if let Some(progress) = &self.progress {
+            for (i, job) in jobs.iter().enumerate() {
+                let done = self.checkpoint.as_ref().is_some_and(|c| c.is_job_done(i));
+                if !done && job.opts.before.is_none() {
+                    Run::plan(progress, job);
+                }
+            }
+        }
+        for (i, job) in jobs.into_iter().enumerate() {
+            if let Some(checkpoint) = &self.checkpoint {
+                if checkpoint.is_job_done(i) {
+                    trace!("job {} is done in an earlier run", i);
+                    continue;
+                }
+                checkpoint.start_job(i);
+            }
+            let op = job.op.name();
+            if let Some(events) = &self.events {
+                events.job_started(
+                    i,
+                    Event::JobStarted {
+                        op,
+                        sources: job.from.clone(),
+                        dst: job.to.clone(),
+                    },
+                );
+            }
+            let (mut stats, result) = self.run_job(job, true);
+            let error = result.as_ref().err().map(|e| format!("{:#}", e));
+            if let Some(events) = &self.events {
+                events.job_finished(error.clone());
+            }
+            // a failed job still reports what it did before failing
+            if result.is_err() {
+                stats.failed += 1;
+            }
+            self.report.total.add(&stats);
+            self.report.jobs.push(JobReport {
+                job: i,
+                op,
+                error,
+                stats,
+            });
+            result?;
+            if let Some(checkpoint) = &self.checkpoint {
+                checkpoint.job_done()?;
+            }
+        }
+        if let Some(checkpoint) = &self.checkpoint {
+            checkpoint.finish()?;
+        }
+        Ok(())
+    }
+
+    /// adds the files of `job` to the progress totals. a job which can't be
+    /// planned fails once it's run
+    fn plan(progress: &Progress, job: &Job) {
+        if let Ok(paths) = job.plan() {
+            let bytes = paths.iter().map(|p| FileOp::size_of(&p.from)).sum();
+            progress.planned(paths.len() as u64, bytes);
+        }
+    }
+
+    /// runs one job, it isn't added to the report
+    pub fn job(&self, job: Job) -> Result<Stats> {
+        let (stats, result) = self.run_job(job, false);
+        result.map(|()| stats)
+    }
+
+    /// `run_jobs` plans jobs up front, except those with a before hook, as it
+    /// can make their files. the stats of what was done are kept on failure
+    fn run_job(&self, job: Job, planned_up_front: bool) -> (Stats, Result<()>) {
+        let dst = job.to.clone();
+        let (before, after) = (job.opts.before.clone(), job.opts.after.clone());
+        if let Some(before) = &before {
+            if let Err(e) = hooks::run(before, &[("dst", &dst)]) {
+                return (Stats::default(), Err(e));
+            }
+        }
+        if let Some(progress) = &self.progress {
+            if !planned_up_front || before.is_some() {
+                Run::plan(progress, &job);
+            }
+        }
+        let (staged, keep_previous) = (job.opts.staged, job.opts.keep_previous);
+        let file_ops = FileOp::from(job);
+        let (stats, result) = if staged && !(file_ops.len() == 1 && file_ops[0].is_file()) {
+            self.staged(file_ops, &dst, keep_previous)
+        } else {
+            self.process(file_ops, true)
+        };
+        if result.is_err() {
+            return (stats, result);
+        }
+        match &after {
+            Some(after) => (stats, hooks::run(after, &[("dst", &dst)])),
+            None => (stats, Ok(())),
+        }
+    }
+
+    /// file ops are journaled, and `checkpointed` ones are checkpointed too. what
+    /// was done before a failed file op is counted
+    fn process(&self, file_ops: Vec<FileOp>, checkpointed: bool) -> (Stats, Result<()>) {
+        let mut stats = Stats::default();
+        for mut file_op in file_ops {
+            if let Some(journal) = &self.journal {
+                file_op.set_journal(journal.clone());
+            }
+            if let Some(checkpoint) = self.checkpoint.as_ref().filter(|_| checkpointed) {
+                file_op.set_checkpoint(checkpoint.clone());
+            }
+            if let Some(events) = &self.events {
+                file_op.set_events(events.clone());
+            }
+            if let Some(progress) = &self.progress {
+                file_op.set_progress(progress.clone());
+            }
+            let (op_stats, result) = file_op.process_counted();
+            stats.add(&op_stats);
+            if result.is_err() {
+                return (stats, result);
+            }
+        }
+        (stats, Ok(()))
+    }
+
+    /// the destination dir is replaced by exactly what the job produces. all its
+    /// sources are built in one staging dir next to it, which is swapped into
+    /// place once complete, so a failure leaves the old dir untouched.
+    fn staged(
+        &self,
+        file_ops: Vec<FileOp>,
+        dst: &Path,
+        keep_previous: bool,
+    ) -> (Stats, Result<()>) {
+        let moves = file_ops.iter().any(|file_op| file_op.is_move());
+        let staging = match FileOp::staging_dir(dst, moves) {
+            Ok(staging) => staging,
+            Err(e) => return (Stats::default(), Err(e)),
+        };
+        let file_ops = file_ops
+            .into_iter()
+            .map(|file_op| file_op.staged_in(dst, &staging))
+            .collect();
+        // files in the staging dir are journaled too, so undo puts moved ones back
+        let (stats, result) = self.process(file_ops, false);
+        if let Err(e) = result {
+            if moves {
+                let moved_to = format!("files moved so far are left in {}", staging.display());
+                return (stats, Err(e.context(moved_to)));
+            }
+            let _ = fs::remove_dir_all(&staging);
+            return (stats, Err(e));
+        }
+        (stats, self.swap_in(&staging, dst, keep_previous))
+    }
+
+    fn swap_in(&self, staging: &Path, dst: &Path, keep_previous: bool) -> Result<()> {
+        if !staging.exists() {
+            match &self.journal {
+                Some(journal) => journal.create_dir_all(staging)?,
+                None => fs::create_dir_all(staging)?,
+            }
+        }
+        FileOp::swap_in(staging, dst, keep_previous, self.journal.as_deref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::job::OnEmpty;
+    use std::path::PathBuf;
+    use tempfile::TempDir;
+
+    #[test]
+    fn failed_job_keeps_stats() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let src = tmp_dir.path().join("a.txt");
+        fs::write(&src, "aaa")?;
+        let job = Job::copy(&src, tmp_dir.path().join("dst"))
+            .source(tmp_dir.path().join("b.txt"))
+            .on_empty(OnEmpty::Error);
+        let mut run = Run::new();
+        assert!(run.jobs(vec![job]).is_err());
+        let stats = &run.report().jobs[0].stats;
+        assert_eq!((stats.copied, stats.bytes, stats.failed), (1, 3, 1));
+        assert_eq!(run.report().total.copied, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn staged_dir_swapped_in() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let dst = tmp_dir.path().join("dst");
+        let staged = |keep_previous| {
+            let job = Job::copy("./test_files/test_src_dst_paths", &dst)
+                .staged(true)
+                .keep_previous(keep_previous);
+            Run::new().job(job)
+        };
+        staged(false)?;
+        assert!(dst.join("d1").join("d12").join("f12.file").exists());
+        fs::write(dst.join("stale_file"), "stale")?;
+        staged(true)?;
+        assert!(!dst.join("stale_file").exists());
+        assert!(dst.join("f1.file").exists());
+        let prev = tmp_dir.path().join("dst.prev");
+        assert!(prev.join("stale_file").exists());
+        staged(false)?;
+        assert!(dst.join("f1.file").exists());
+        assert!(prev.exists());
+        let names: Vec<_> = fs::read_dir(tmp_dir.path())?
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(names.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn staged_filter_swapped_in() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let dst = tmp_dir.path().join("dst");
+        fs::create_dir_all(&dst)?;
+        fs::write(dst.join("stale_file"), "stale")?;
+        let job = Job::copy("./test_files/test_src_dst_paths/**.file", &dst).staged(true);
+        Run::new().job(job)?;
+        assert!(dst.join("f12.file").exists());
+        assert!(!dst.join("f3.img").exists());
+        assert!(!dst.join("stale_file").exists());
+        assert_eq!(fs::read_dir(tmp_dir.path())?.count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn staged_sources_swapped_in_once() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let dst = tmp_dir.path().join("dst");
+        fs::create_dir_all(&dst)?;
+        fs::write(dst.join("stale_file"), "stale")?;
+        let src = PathBuf::from("./test_files/test_src_dst_paths");
+        let job = Job::copy(src.join("d1"), &dst)
+            .source(src.join("f1.file"))
+            .staged(true);
+        Run::new().job(job)?;
+        assert!(dst.join("d1").join("d12").join("f12.file").exists());
+        assert!(dst.join("f1.file").exists());
+        assert!(!dst.join("stale_file").exists());
+        assert_eq!(fs::read_dir(tmp_dir.path())?.count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn journaled_staged_swap_undone() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let root = tmp_dir.path().join("journal");
+        let dst = tmp_dir.path().join("dst");
+        fs::create_dir_all(&dst)?;
+        fs::write(dst.join("old_file"), "old")?;
+        let job = Job::copy("./test_files/test_src_dst_paths", &dst).staged(true);
+        Run::new().journal(Journal::new(&root)).job(job)?;
+        assert!(!dst.join("old_file").exists());
+        crate::journal::undo(&root, None)?;
+        assert_eq!(fs::read_to_string(dst.join("old_file"))?, "old");
+        assert_eq!(fs::read_dir(&dst)?.count(), 1);
+        assert_eq!(fs::read_dir(tmp_dir.path())?.count(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn journaled_staged_move_undone() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let root = tmp_dir.path().join("journal");
+        let src = tmp_dir.path().join("src");
+        fs::create_dir_all(src.join("d1"))?;
+        fs::write(src.join("f1.file"), "f1")?;
+        fs::write(src.join("d1").join("f2.file"), "f2")?;
+        let dst = tmp_dir.path().join("dst");
+        fs::create_dir_all(&dst)?;
+        fs::write(dst.join("old_file"), "old")?;
+        let job = Job::move_(src.join("**.file"), &dst).staged(true);
+        Run::new().journal(Journal::new(&root)).job(job)?;
+        assert!(dst.join("f2.file").exists());
+        assert!(!src.join("f1.file").exists());
+        crate::journal::undo(&root, None)?;
+        assert_eq!(fs::read_to_string(src.join("f1.file"))?, "f1");
+        assert_eq!(fs::read_to_string(src.join("d1").join("f2.file"))?, "f2");
+        assert_eq!(fs::read_to_string(dst.join("old_file"))?, "old");
+        assert_eq!(fs::read_dir(&dst)?.count(), 1);
+        assert_eq!(fs::read_dir(tmp_dir.path())?.count(), 3);
+        Ok(())
+    }
+}
@@ -0,0 +1,169 @@
+use crate::journal::encoded_path;
+use anyhow::Result;
+use log::info;
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::fs::File;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+
+/// this many of the slowest files are kept
+const SLOWEST: usize = 5;
+
+/// counts of what a job or a whole run did
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct Stats {
+    /// files and dirs matched by the sources
+    pub matched: u64,
+    pub copied: u64,
+    pub linked: u64,
+    pub moved: u64,
+    pub skipped: u64,
+    /// destination files which existed and were replaced
+    pub overwritten: u64,
+    pub failed: u64,
+    /// bytes of copied, linked and moved files
+    pub bytes: u64,
+    pub elapsed_ms: u64,
+    pub slowest: Vec<SlowFile>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SlowFile {
+    #[serde(serialize_with = "encoded_path::serialize")]
+    pub src: PathBuf,
+    pub size: u64,
+    pub duration_ms: u64,
+}
+
+impl Stats {
+    pub fn add(&mut self, other: &Stats) {
+        self.matched += other.matched;
+        self.copied += other.copied;
+        self.linked += other.linked;
+        self.moved += other.moved;
+        self.skipped += other.skipped;
+        self.overwritten += other.overwritten;
+        self.failed += other.failed;
+        self.bytes += other.bytes;
+        self.elapsed_ms += other.elapsed_ms;
+        for file in &other.slowest {
+            self.add_slow(file.clone());
+        }
+    }
+
+    pub fn add_slow(&mut self, file: SlowFile) {
+        self.slowest.push(file);
+        self.slowest.sort_by_key(|f| Reverse(f.duration_ms));
+        self.slowest.truncate(SLOWEST);
+    }
+
+    pub fn line(&self) -> String {
+        format!(
+            "{} matched, {} copied, {} linked, {} moved, {} skipped, {} overwritten, \
+             {} failed, {} bytes in {} ms",
+            self.matched,
+            self.copied,
+            self.linked,
+            self.moved,
+            self.skipped,
+            self.overwritten,
+            self.failed,
+            self.bytes,
+            self.elapsed_ms
+        )
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct JobReport {
+    pub job: usize,
+    pub op: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(flatten)]
+    pub stats: Stats,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct Report {
+    pub jobs: Vec<JobReport>,
+    pub total: Stats,
+}
+
+impl Report {
+    /// shown on stderr when it's a terminal and not `quiet`, logged otherwise
+    pub fn print(&self, quiet: bool) {
+        let mut text = String::new();
+        for job in &self.jobs {
+            text.push_str(&format!(
+                "job {} ({}): {}\n",
+                job.job,
+                job.op,
+                job.stats.line()
+            ));
+            if let Some(error) = &job.error {
+                text.push_str(&format!("    error: {}\n", error));
+            }
+        }
+        text.push_str(&format!("total: {}\n", self.total.line()));
+        if !self.total.slowest.is_empty() {
+            text.push_str("slowest files:\n");
+            for file in &self.total.slowest {
+                text.push_str(&format!(
+                    "    {} ms, {} bytes, {}\n",
+                    file.duration_ms,
+                    file.size,
+                    file.src.display()
+                ));
+            }
+        }
+        if !quiet && io::stderr().is_terminal() {
+            eprint!("{}", text);
+        } else {
+            info!("run summary:\n{}", text);
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        serde_json::to_writer_pretty(File::create(path)?, self)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slow(name: &str, duration_ms: u64) -> SlowFile {
+        SlowFile {
+            src: PathBuf::from(name),
+            size: 1,
+            duration_ms,
+        }
+    }
+
+    #[test]
+    fn stats_added() {
+        let mut total = Stats::default();
+        let mut job = Stats {
+            matched: 3,
+            copied: 2,
+            skipped: 1,
+            bytes: 10,
+            ..Default::default()
+        };
+        for i in 0..4 {
+            job.add_slow(slow(&format!("a{}", i), i));
+        }
+        total.add(&job);
+        job.add_slow(slow("b", 10));
+        total.add(&job);
+        assert_eq!(total.matched, 6);
+        assert_eq!(total.copied, 4);
+        assert_eq!(total.skipped, 2);
+        assert_eq!(total.bytes, 20);
+        let slowest: Vec<u64> = total.slowest.iter().map(|f| f.duration_ms).collect();
+        assert_eq!(slowest, [10, 3, 3, 2, 2]);
+    }
+}
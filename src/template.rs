@@ -0,0 +1,82 @@
+//! puts variables in the contents of text files, like a generated setup.txt
+//! or config xml which names the output dir of its configuration
+
+use crate::job::Render;
+use anyhow::{bail, Result};
+use regex::{Captures, Regex};
+
+/// replaces "{name}" placeholders in `text` with values of `variables`.
+/// names are like "OutDir", "out_dir" or "out.dir", so braces of code or
+/// json are left alone. a placeholder without a variable is kept, or is an
+/// error when `strict`.
+pub fn render(text: &str, variables: &[(String, String)], strict: bool) -> Result<String> {
+    let re = Regex::new(r"\{([A-Za-z_][A-Za-z0-9_.-]*)\}").unwrap();
+    let mut unresolved = Vec::new();
+    let rendered = re.replace_all(text, |caps: &Captures| {
+        match variables.iter().find(|(name, _)| name == &caps[1]) {
+            Some((_, value)) => value.clone(),
+            None => {
+                unresolved.push(caps[0].to_owned());
+                caps[0].to_owned()
+            }
+        }
+    });
+    if strict && !unresolved.is_empty() {
+        bail!("unresolved placeholders: {}", unresolved.join(", "));
+    }
+    Ok(rendered.into_owned())
+}
+
+/// `contents` of a file, rendered. they have to be utf-8 text
+pub fn render_contents(contents: Vec<u8>, render_opts: &Render) -> Result<Vec<u8>> {
+    let text = match String::from_utf8(contents) {
+        Ok(text) => text,
+        Err(_) => bail!("it isn't utf-8 text"),
+    };
+    let rendered = render(&text, &render_opts.variables, render_opts.strict)?;
+    Ok(rendered.into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn variables() -> Vec<(String, String)> {
+        vec![
+            ("OutDir".to_owned(), "bin/Release".to_owned()),
+            ("Platform".to_owned(), "x64".to_owned()),
+        ]
+    }
+
+    #[test]
+    fn placeholders_replaced() -> Result<()> {
+        assert_eq!(
+            render("path={OutDir}/{Platform}\n{\"a\": 1}", &variables(), false)?,
+            "path=bin/Release/x64\n{\"a\": 1}"
+        );
+        assert_eq!(
+            render("{OutDir} {Unknown}", &variables(), false)?,
+            "bin/Release {Unknown}"
+        );
+        let e = render("{OutDir} {Unknown}", &variables(), true).unwrap_err();
+        assert_eq!(e.to_string(), "unresolved placeholders: {Unknown}");
+        Ok(())
+    }
+
+    #[test]
+    fn contents_rendered() -> Result<()> {
+        let render_opts = Render {
+            variables: variables(),
+            ..Default::default()
+        };
+        assert_eq!(
+            render_contents(b"dir={OutDir}".to_vec(), &render_opts)?,
+            b"dir=bin/Release"
+        );
+        assert!(render_contents(b"\xff\xfe{OutDir}".to_vec(), &render_opts).is_err());
+        assert!(render_opts.renders(Path::new("a/Setup.TXT")));
+        assert!(!render_opts.renders(Path::new("a/b.png")));
+        Ok(())
+    }
+}
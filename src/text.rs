@@ -0,0 +1,125 @@
+//! normalizes text files while they're copied, so the same sources deploy
+//! the same way to linux and windows, whatever the checkout produced
+
+use crate::job::{self, Eol, TextFilter};
+use anyhow::Result;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+const BOM: &[u8] = b"\xef\xbb\xbf";
+/// how much of a file is read to tell whether it's text
+const SNIFF_LEN: u64 = 8192;
+
+/// by extension when the filter lists them, or else by the start of the file
+pub fn is_text(path: &Path, filter: &TextFilter) -> Result<bool> {
+    if !filter.extensions.is_empty() {
+        return Ok(job::has_extension(path, &filter.extensions));
+    }
+    let mut start = Vec::new();
+    File::open(path)?.take(SNIFF_LEN).read_to_end(&mut start)?;
+    Ok(looks_like_text(&start))
+}
+
+/// no NUL bytes, and utf-8 except for a char cut off at the end
+fn looks_like_text(start: &[u8]) -> bool {
+    if start.contains(&0) {
+        return false;
+    }
+    match std::str::from_utf8(start) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    }
+}
+
+/// `contents` with a bom stripped, trailing whitespace trimmed and line
+/// endings replaced, as `filter` says
+pub fn normalize(contents: &[u8], filter: &TextFilter) -> Vec<u8> {
+    let contents = match filter.strip_bom {
+        true => contents.strip_prefix(BOM).unwrap_or(contents),
+        false => contents,
+    };
+    let mut normalized = Vec::with_capacity(contents.len());
+    let mut lines = contents.split(|b| *b == b'\n').peekable();
+    while let Some(line) = lines.next() {
+        // the last line has no line ending
+        let last = lines.peek().is_none();
+        let (mut line, crlf) = match line.strip_suffix(b"\r") {
+            Some(line) if !last => (line, true),
+            _ => (line, false),
+        };
+        if filter.trim_trailing_whitespace {
+            while let [rest @ .., b' ' | b'\t'] = line {
+                line = rest;
+            }
+        }
+        normalized.extend_from_slice(line);
+        if !last {
+            normalized.extend_from_slice(match filter.eol {
+                Some(eol) => eol_bytes(eol),
+                None if crlf => b"\r\n",
+                None => b"\n",
+            });
+        }
+    }
+    normalized
+}
+
+fn eol_bytes(eol: Eol) -> &'static [u8] {
+    match eol {
+        Eol::Lf => b"\n",
+        Eol::Crlf => b"\r\n",
+        Eol::Native if cfg!(windows) => b"\r\n",
+        Eol::Native => b"\n",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn text_normalized() {
+        let contents = b"\xef\xbb\xbfa  \r\nb\t\nc \r";
+        let filter = TextFilter {
+            eol: Some(Eol::Lf),
+            ..Default::default()
+        };
+        assert_eq!(normalize(contents, &filter), b"\xef\xbb\xbfa  \nb\t\nc \r");
+        let filter = TextFilter {
+            eol: Some(Eol::Crlf),
+            strip_bom: true,
+            trim_trailing_whitespace: true,
+            ..Default::default()
+        };
+        assert_eq!(normalize(contents, &filter), b"a\r\nb\r\nc \r");
+        let filter = TextFilter {
+            trim_trailing_whitespace: true,
+            ..Default::default()
+        };
+        assert_eq!(normalize(contents, &filter), b"\xef\xbb\xbfa\r\nb\nc \r");
+    }
+
+    #[test]
+    fn text_detected() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let shader = tmp_dir.path().join("a.glsl");
+        let image = tmp_dir.path().join("b.png");
+        fs::write(&shader, "void main() {}\n")?;
+        fs::write(&image, b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR")?;
+        let sniffed = TextFilter::default();
+        assert!(is_text(&shader, &sniffed)?);
+        assert!(!is_text(&image, &sniffed)?);
+        // a multi byte char cut off by the sniff is still text
+        assert!(looks_like_text("aé".as_bytes().split_last().unwrap().1));
+        let listed = TextFilter {
+            extensions: vec!["png".to_owned()],
+            ..Default::default()
+        };
+        assert!(!is_text(&shader, &listed)?);
+        assert!(is_text(&image, &listed)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,477 @@
+//! runs jobs of a json file, then applies changes of their sources as they
+//! happen: changed and new files are transferred again, renamed files are
+//! renamed in the destination, and with `mirror` removed files are removed.
+//! the before and after hooks of a job run around its changes
+
+use crate::hooks;
+use crate::job::{Job, JobOptions};
+use crate::json_parser::load_job_file;
+use crate::operations::{FileOp, Paths};
+use crate::run::Run;
+use crate::stats::Stats;
+use anyhow::Result;
+use log::{error, info, trace, warn};
+use notify::event::{AccessKind, AccessMode, ModifyKind, RenameMode};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_millis(300);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WatchOptions {
+    /// files removed from sources are removed from destinations too
+    pub mirror: bool,
+    /// changes are applied once sources didn't change for this long
+    pub debounce: Duration,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        Self {
+            mirror: false,
+            debounce: DEFAULT_DEBOUNCE,
+        }
+    }
+}
+
+/// runs all jobs, then applies changes until watching fails. when a json
+/// file changes, it's loaded again and all jobs run again. a json file which
+/// can't be loaded then is logged, and its earlier jobs are watched on.
+pub fn watch(
+    json_file: &Path,
+    variables: Option<Vec<(String, String)>>,
+    opts: &JobOptions,
+    watch_opts: &WatchOptions,
+) -> Result<()> {
+    let load = || Watched::load(json_file, variables.clone(), opts, watch_opts.mirror);
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    let mut watched = load()?;
+    let mut roots = watched.roots();
+    watch_roots(&mut watcher, &roots);
+    loop {
+        let mut changes = Changes::default();
+        changes.add(rx.recv()?);
+        // a save or a checkout is many events, they're applied together
+        while let Ok(event) = rx.recv_timeout(watch_opts.debounce) {
+            changes.add(event);
+        }
+        trace!("{:?}", changes);
+        if watched.json_files.iter().any(|f| changes.paths.contains(f)) {
+            info!("json file changed, running all jobs again");
+            match load() {
+                Ok(loaded) => {
+                    for root in roots.keys() {
+                        let _ = watcher.unwatch(root);
+                    }
+                    watched = loaded;
+                    roots = watched.roots();
+                    watch_roots(&mut watcher, &roots);
+                }
+                // the next change of the json file may fix it
+                Err(e) => error!("loading json file failed, keeping its jobs: {:#}", e),
+            }
+            continue;
+        }
+        match watched.apply(&changes) {
+            Ok(stats) if stats.matched > 0 => info!("changes applied: {}", stats.line()),
+            Ok(_) => {}
+            // the next change may fix it, watching goes on
+            Err(e) => error!("applying changes failed: {:#}", e),
+        }
+    }
+}
+
+/// a root which doesn't exist is skipped, it may be created later by other jobs
+fn watch_roots(watcher: &mut RecommendedWatcher, roots: &BTreeMap<PathBuf, bool>) {
+    for (root, recursive) in roots {
+        let mode = if *recursive {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        };
+        match watcher.watch(root, mode) {
+            Ok(()) => info!("watching {}", root.display()),
+            Err(e) => warn!("can't watch {}: {}", root.display(), e),
+        }
+    }
+}
+
+/// paths changed since the last changes were applied
+#[derive(Debug, Default)]
+struct Changes {
+    paths: BTreeSet<PathBuf>,
+    renames: Vec<(PathBuf, PathBuf)>,
+}
+
+impl Changes {
+    fn add(&mut self, event: notify::Result<Event>) {
+        let event = match event {
+            Ok(event) => event,
+            Err(e) => {
+                warn!("watch error: {}", e);
+                return;
+            }
+        };
+        match event.kind {
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+                if let [from, to] = &event.paths[..] {
+                    self.renames.push((from.clone(), to.clone()));
+                }
+            }
+            // reading sources, also by esycpy itself, changes nothing
+            EventKind::Access(kind) if kind != AccessKind::Close(AccessMode::Write) => return,
+            _ => {}
+        }
+        self.paths.extend(event.paths);
+    }
+}
+
+/// what changes of a source do to its destinations
+#[derive(Debug, Default)]
+struct Update {
+    plan: BTreeMap<PathBuf, PathBuf>,
+    renamed: Vec<(PathBuf, PathBuf)>,
+    removed: Vec<PathBuf>,
+    transferred: Vec<Paths>,
+}
+
+impl Update {
+    fn is_empty(&self) -> bool {
+        self.renamed.is_empty() && self.removed.is_empty() && self.transferred.is_empty()
+    }
+}
+
+#[derive(Debug)]
+struct Source {
+    file_op: FileOp,
+    /// destinations of source files, as they were when changes were last applied
+    plan: BTreeMap<PathBuf, PathBuf>,
+}
+
+impl Source {
+    /// a source which can't be planned is logged, and planned again on changes
+    fn new(file_op: FileOp) -> Self {
+        let plan = Source::plan_of(&file_op).unwrap_or_else(|e| {
+            error!("planning {} failed: {:#}", file_op.root().0.display(), e);
+            BTreeMap::new()
+        });
+        Self { file_op, plan }
+    }
+
+    /// only existing files, a source removed since may be still planned
+    fn plan_of(file_op: &FileOp) -> Result<BTreeMap<PathBuf, PathBuf>> {
+        if !file_op.root().0.exists() {
+            return Ok(BTreeMap::new());
+        }
+        Ok(file_op
+            .plan()?
+            .into_iter()
+            .filter(|p| p.from.exists())
+            .map(|p| (p.from, p.to))
+            .collect())
+    }
+
+    /// what applying `changes` does, nothing is changed yet
+    fn update(&self, changes: &Changes, mirror: bool) -> Result<Update> {
+        let plan = Source::plan_of(&self.file_op)?;
+        let mut renamed = Vec::new();
+        let mut renamed_srcs = BTreeSet::new();
+        for (from, to) in &changes.renames {
+            if let (Some(old_dst), Some(new_dst)) = (self.plan.get(from), plan.get(to)) {
+                if old_dst.is_file() && !new_dst.exists() {
+                    renamed.push((old_dst.clone(), new_dst.clone()));
+                    renamed_srcs.insert(to);
+                }
+            }
+        }
+        let mut removed = Vec::new();
+        if mirror {
+            let mut dsts: BTreeSet<&PathBuf> = plan.values().collect();
+            // renamed ones are moved away, not removed
+            dsts.extend(renamed.iter().map(|(old_dst, _)| old_dst));
+            for (src, dst) in &self.plan {
+                if !plan.contains_key(src) && !dsts.contains(dst) && dst.is_file() {
+                    removed.push(dst.clone());
+                }
+            }
+        }
+        let transferred = plan
+            .iter()
+            .filter(|(src, _)| !renamed_srcs.contains(src))
+            .filter(|(src, _)| !self.plan.contains_key(*src) || changes.paths.contains(*src))
+            .map(|(from, to)| Paths {
+                from: from.clone(),
+                to: to.clone(),
+            })
+            .collect();
+        Ok(Update {
+            plan,
+            renamed,
+            removed,
+            transferred,
+        })
+    }
+
+    fn apply(&mut self, update: Update) -> Result<Stats> {
+        self.plan = update.plan;
+        for (old_dst, new_dst) in &update.renamed {
+            fs::create_dir_all(new_dst.parent().unwrap())?;
+            fs::rename(old_dst, new_dst)?;
+            info!("renamed {} to {}", old_dst.display(), new_dst.display());
+        }
+        for dst in &update.removed {
+            fs::remove_file(dst)?;
+            info!("removed {}", dst.display());
+        }
+        if update.transferred.is_empty() {
+            return Ok(Stats::default());
+        }
+        self.file_op.process_paths(&update.transferred)
+    }
+}
+
+/// a job and its sources, which are updated together
+#[derive(Debug)]
+struct WatchedJob {
+    job: Job,
+    sources: Vec<Source>,
+}
+
+impl WatchedJob {
+    /// changes are applied between the before and after hooks of the job. a
+    /// staged job is run again, its destination is built from all sources
+    fn apply(&mut self, changes: &Changes, mirror: bool) -> Result<Stats> {
+        let updates = self
+            .sources
+            .iter()
+            .map(|source| source.update(changes, mirror))
+            .collect::<Result<Vec<Update>>>()?;
+        if updates.iter().all(Update::is_empty) {
+            for (source, update) in self.sources.iter_mut().zip(updates) {
+                source.plan = update.plan;
+            }
+            return Ok(Stats::default());
+        }
+        if self.job.opts.staged {
+            for (source, update) in self.sources.iter_mut().zip(updates) {
+                source.plan = update.plan;
+            }
+            return Run::new().job(self.job.clone());
+        }
+        let dst = &self.job.to;
+        if let Some(before) = &self.job.opts.before {
+            hooks::run(before, &[("dst", dst)])?;
+        }
+        let mut stats = Stats::default();
+        for (source, update) in self.sources.iter_mut().zip(updates) {
+            stats.add(&source.apply(update)?);
+        }
+        if let Some(after) = &self.job.opts.after {
+            hooks::run(after, &[("dst", dst)])?;
+        }
+        Ok(stats)
+    }
+}
+
+#[derive(Debug)]
+struct Watched {
+    json_files: Vec<PathBuf>,
+    jobs: Vec<WatchedJob>,
+    mirror: bool,
+}
+
+impl Watched {
+    /// runs all jobs once. a failed job is logged, its changes may fix it
+    fn load(
+        json_file: &Path,
+        variables: Option<Vec<(String, String)>>,
+        opts: &JobOptions,
+        mirror: bool,
+    ) -> Result<Self> {
+        let job_file = load_job_file(json_file, variables, opts)?;
+        let mut run = Run::new();
+        match run.jobs(job_file.jobs.clone()) {
+            Ok(()) => info!("all jobs done: {}", run.report().total.line()),
+            Err(e) => error!("running jobs failed: {:#}", e),
+        }
+        let jobs = job_file
+            .jobs
+            .into_iter()
+            .map(|job| WatchedJob {
+                sources: FileOp::from(job.clone())
+                    .into_iter()
+                    .map(Source::new)
+                    .collect(),
+                job,
+            })
+            .collect();
+        Ok(Self {
+            json_files: job_file.files,
+            jobs,
+            mirror,
+        })
+    }
+
+    /// dirs to watch, and whether their sub dirs are watched too
+    fn roots(&self) -> BTreeMap<PathBuf, bool> {
+        let mut roots = BTreeMap::new();
+        for json_file in &self.json_files {
+            roots.insert(json_file.parent().unwrap().to_owned(), false);
+        }
+        for source in self.jobs.iter().flat_map(|job| &job.sources) {
+            let (root, recursive) = source.file_op.root();
+            *roots.entry(root.to_owned()).or_insert(false) |= recursive;
+        }
+        roots
+    }
+
+    fn apply(&mut self, changes: &Changes) -> Result<Stats> {
+        let mut stats = Stats::default();
+        for job in &mut self.jobs {
+            stats.add(&job.apply(changes, self.mirror)?);
+        }
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn changes(paths: &[&Path], renames: &[(&Path, &Path)]) -> Changes {
+        let mut changes = Changes::default();
+        for path in paths {
+            changes.add(Ok(Event::new(EventKind::Any).add_path(path.to_path_buf())));
+        }
+        for (from, to) in renames {
+            changes.add(Ok(Event::new(EventKind::Modify(ModifyKind::Name(
+                RenameMode::Both,
+            )))
+            .add_path(from.to_path_buf())
+            .add_path(to.to_path_buf())));
+        }
+        changes
+    }
+
+    #[test]
+    fn reads_ignored() {
+        let mut changes = Changes::default();
+        let read = EventKind::Access(AccessKind::Close(AccessMode::Read));
+        changes.add(Ok(Event::new(read).add_path(PathBuf::from("a"))));
+        let written = EventKind::Access(AccessKind::Close(AccessMode::Write));
+        changes.add(Ok(Event::new(written).add_path(PathBuf::from("b"))));
+        assert_eq!(changes.paths, BTreeSet::from([PathBuf::from("b")]));
+    }
+
+    #[test]
+    fn changes_applied() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let base = tmp_dir.path().canonicalize()?;
+        let src = base.join("assets");
+        let dst = base.join("out");
+        fs::create_dir_all(src.join("sub"))?;
+        fs::write(src.join("a.png"), "a")?;
+        fs::write(src.join("sub").join("b.png"), "b")?;
+        fs::write(src.join("c.png"), "c")?;
+        let json_file = base.join("jobs.json");
+        fs::write(
+            &json_file,
+            r#"{
+                "variables_in_use": [],
+                "jobs": [{ "todo": "copy", "src": "assets", "dst": "out" }]
+            }"#,
+        )?;
+        let mut watched = Watched::load(&json_file, None, &JobOptions::default(), true)?;
+        assert!(dst.join("sub").join("b.png").is_file());
+        assert_eq!(
+            watched.roots(),
+            BTreeMap::from([(base.clone(), false), (src.clone(), true)])
+        );
+
+        fs::write(src.join("a.png"), "changed")?;
+        fs::write(src.join("d.png"), "new")?;
+        fs::remove_file(src.join("c.png"))?;
+        fs::rename(src.join("sub").join("b.png"), src.join("e.png"))?;
+        let stats = watched.apply(&changes(
+            &[&src.join("a.png"), &src.join("d.png"), &src.join("c.png")],
+            &[(&src.join("sub").join("b.png"), &src.join("e.png"))],
+        ))?;
+        assert_eq!(stats.copied, 2);
+        assert_eq!(fs::read_to_string(dst.join("a.png"))?, "changed");
+        assert_eq!(fs::read_to_string(dst.join("d.png"))?, "new");
+        assert_eq!(fs::read_to_string(dst.join("e.png"))?, "b");
+        assert!(!dst.join("c.png").exists());
+        assert!(!dst.join("sub").join("b.png").exists());
+
+        // without changes, nothing is transferred again
+        assert_eq!(watched.apply(&Changes::default())?.matched, 0);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hooks_run_around_changes() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let base = tmp_dir.path().canonicalize()?;
+        let src = base.join("assets");
+        fs::create_dir_all(&src)?;
+        fs::write(src.join("a.png"), "a")?;
+        let json_file = base.join("jobs.json");
+        fs::write(
+            &json_file,
+            r#"{
+                "variables_in_use": [],
+                "jobs": [{
+                    "todo": "copy", "src": "assets", "dst": "out",
+                    "after": "echo done >> {dst}/../after.log"
+                }]
+            }"#,
+        )?;
+        let mut watched = Watched::load(&json_file, None, &JobOptions::default(), false)?;
+        let after_runs = || fs::read_to_string(base.join("after.log")).map(|s| s.lines().count());
+        assert_eq!(after_runs()?, 1);
+
+        fs::write(src.join("a.png"), "changed")?;
+        watched.apply(&changes(&[&src.join("a.png")], &[]))?;
+        assert_eq!(after_runs()?, 2);
+
+        // without changes, the job and its hooks don't run
+        watched.apply(&Changes::default())?;
+        assert_eq!(after_runs()?, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn load_failures_returned() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let json_file = tmp_dir.path().join("jobs.json");
+        let load = || Watched::load(&json_file, None, &JobOptions::default(), false);
+        fs::write(&json_file, r#"{ "jobs": [ { "src": "#)?;
+        assert!(load().is_err());
+
+        // a source which can't be planned doesn't stop watching the others
+        fs::create_dir_all(tmp_dir.path().join("assets"))?;
+        fs::write(tmp_dir.path().join("assets").join("a.png"), "a")?;
+        fs::write(
+            &json_file,
+            r#"{
+                "variables_in_use": [],
+                "jobs": [
+                    { "todo": "copy", "src": "assets", "dst": "bad", "rename": { "match": "(", "to": "" } },
+                    { "todo": "copy", "src": "assets", "dst": "out" }
+                ]
+            }"#,
+        )?;
+        let watched = load()?;
+        assert_eq!(watched.jobs.len(), 2);
+        assert!(watched.jobs[0].sources[0].plan.is_empty());
+        assert_eq!(watched.jobs[1].sources[0].plan.len(), 1);
+        Ok(())
+    }
+}
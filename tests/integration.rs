@@ -1,13 +1,19 @@
 use anyhow::Result;
 use assert_cmd::Command;
-use fs_extra;
 use std::path::Path;
 use tempfile::TempDir;
 use walkdir::WalkDir;
 
+/// the binary, its log file and journals are kept out of the home dir
+fn esycpy() -> Command {
+    let mut cmd = Command::cargo_bin("esycpy").unwrap();
+    cmd.env("ESYCPY_DIR", env!("CARGO_TARGET_TMPDIR"));
+    cmd
+}
+
 #[test]
 fn test_run() -> Result<()> {
-    let mut cmd = Command::cargo_bin("esycpy")?;
+    let mut cmd = esycpy();
     let out = String::from_utf8(cmd.output()?.stdout)?;
     let err = String::from_utf8(cmd.output()?.stderr)?;
     assert!(err.contains("no arguments given"));
@@ -25,9 +31,9 @@ fn copy_whole_dir_cmdline() -> Result<()> {
     let base = Path::new("./test_files/integration_test_env");
     assert!(base.exists());
     fs_extra::dir::copy(base, &src, &fs_extra::dir::CopyOptions::default())?;
-    let mut cmd = Command::cargo_bin("esycpy")?;
+    let mut cmd = esycpy();
     let out = cmd
-        .args(&[
+        .args([
             "copy",
             "-s",
             src.to_str().unwrap(),
@@ -36,8 +42,8 @@ fn copy_whole_dir_cmdline() -> Result<()> {
         ])
         .output()?;
     let dst = dst.join("integration_test_env");
-    assert!(out.stdout.len() == 0);
-    assert!(out.stderr.len() == 0);
+    assert!(out.stdout.is_empty());
+    assert!(out.stderr.is_empty());
     assert!(dst.join("f1.ext1").exists());
     assert!(dst.join("f5.ext1").exists());
     assert!(dst.join("d2").join("f22.ext1").exists());
@@ -65,9 +71,9 @@ fn move_whole_dir_cmdline() -> Result<()> {
     let base = Path::new("./test_files/integration_test_env");
     assert!(base.exists());
     fs_extra::dir::copy(base, &src, &fs_extra::dir::CopyOptions::default())?;
-    let mut cmd = Command::cargo_bin("esycpy")?;
+    let mut cmd = esycpy();
     let out = cmd
-        .args(&[
+        .args([
             "move",
             "-s",
             src.to_str().unwrap(),
@@ -76,8 +82,8 @@ fn move_whole_dir_cmdline() -> Result<()> {
         ])
         .output()?;
     let dst = dst.join("integration_test_env");
-    assert!(out.stdout.len() == 0);
-    assert!(out.stderr.len() == 0);
+    assert!(out.stdout.is_empty());
+    assert!(out.stderr.is_empty());
     assert!(dst.join("f1.ext1").exists());
     assert!(dst.join("f5.ext1").exists());
     assert!(dst.join("d2").join("f22.ext1").exists());
@@ -105,9 +111,9 @@ fn hardlink_whole_dir_cmdline() -> Result<()> {
     let base = Path::new("./test_files/integration_test_env");
     assert!(base.exists());
     fs_extra::dir::copy(base, &src, &fs_extra::dir::CopyOptions::default())?;
-    let mut cmd = Command::cargo_bin("esycpy")?;
+    let mut cmd = esycpy();
     let out = cmd
-        .args(&[
+        .args([
             "hardlink",
             "-s",
             src.to_str().unwrap(),
@@ -116,8 +122,8 @@ fn hardlink_whole_dir_cmdline() -> Result<()> {
         ])
         .output()?;
     let dst = dst.join("integration_test_env");
-    assert!(out.stdout.len() == 0);
-    assert!(out.stderr.len() == 0);
+    assert!(out.stdout.is_empty());
+    assert!(out.stderr.is_empty());
     assert!(dst.join("f1.ext1").exists());
     assert!(dst.join("f5.ext1").exists());
     assert!(dst.join("d2").join("f22.ext1").exists());
@@ -149,9 +155,9 @@ fn json_arg() -> Result<()> {
         ..Default::default()
     };
     fs_extra::dir::copy(base, &src, &copy_option)?;
-    let mut cmd = Command::cargo_bin("esycpy")?;
+    let mut cmd = esycpy();
     let out = cmd
-        .args(&[
+        .args([
             "--json",
             "./test_files/integration_test_copier.json",
             "-v",
@@ -217,3 +223,387 @@ fn json_arg() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn copy_multiple_sources_cmdline() -> Result<()> {
+    let tmp_dir = TempDir::new().unwrap();
+    let dst = tmp_dir.path().join("dst");
+    let base = Path::new("./test_files/integration_test_env");
+    let mut cmd = esycpy();
+    cmd.args([
+        "copy",
+        "-s",
+        base.join("f1.ext1").to_str().unwrap(),
+        "-s",
+        base.join("d5").to_str().unwrap(),
+        "-d",
+        dst.to_str().unwrap(),
+        base.join("f2.ext1").to_str().unwrap(),
+        base.join("d2").join("*.ext1").to_str().unwrap(),
+    ])
+    .assert()
+    .success();
+    assert!(dst.join("f1.ext1").is_file());
+    assert!(dst.join("f2.ext1").is_file());
+    assert!(dst.join("d5").join("f51.ext1").exists());
+    assert!(dst.join("d5").join("f54.ext2").exists());
+    assert!(dst.join("f21.ext1").exists());
+    assert!(dst.join("f23.ext1").exists());
+    assert!(!dst.join("d21").exists());
+    Ok(())
+}
+
+#[test]
+fn readme_cmdline_examples() -> Result<()> {
+    let tmp_dir = TempDir::new().unwrap();
+    let root = tmp_dir.path();
+    for file in &[
+        "file",
+        "a.txt",
+        "b.txt",
+        "dir1/f1.txt",
+        "dir1/sub/f2.txt",
+        "dir2/a.xml",
+        "dir2/b.txt",
+        "dir3/p.png",
+        "dir3/q.jpg",
+        "audios/x.ogg",
+        "audios/sub/y.ogg",
+        "audios/z.mp3",
+    ] {
+        let path = root.join(file);
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        std::fs::write(path, file)?;
+    }
+    let readme = std::fs::read_to_string("README.md")?;
+    let examples: Vec<&str> = readme
+        .split("USING OPTIONS:")
+        .nth(1)
+        .unwrap()
+        .lines()
+        .map(|l| l.trim())
+        .filter(|l| l.starts_with("> esycpy "))
+        .collect();
+    assert_eq!(examples.len(), 7);
+    for example in examples {
+        let args: Vec<String> = example
+            .split_whitespace()
+            .skip(2)
+            .map(|a| {
+                a.replace("c:/users/example", root.to_str().unwrap())
+                    .replace("/home/example", root.to_str().unwrap())
+            })
+            .collect();
+        esycpy().args(&args).assert().success();
+    }
+    assert!(root.join("new_hard_link").is_file());
+    assert!(root
+        .join("dir_with_hlinks")
+        .join("sub")
+        .join("f2.txt")
+        .exists());
+    assert!(root.join("dir_move_in_here").join("a.xml").exists());
+    assert!(!root.join("dir2").join("a.xml").exists());
+    assert!(root.join("only_oggs").join("x.ogg").exists());
+    assert!(root.join("only_oggs").join("y.ogg").exists());
+    assert!(!root.join("only_oggs").join("z.mp3").exists());
+    assert!(root.join("out").join("a.txt").is_file());
+    assert!(root.join("out").join("p.png").exists());
+    assert!(root.join("out").join("b.txt").is_file());
+    assert!(root.join("out").join("f1.txt").is_file());
+    Ok(())
+}
+
+#[test]
+fn missing_destination_cmdline() -> Result<()> {
+    esycpy().args(["copy", "only_source"]).assert().failure();
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn copy_non_utf8_names_cmdline() -> Result<()> {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    let tmp_dir = TempDir::new().unwrap();
+    let src = tmp_dir.path().join(OsStr::from_bytes(b"src\xff"));
+    std::fs::create_dir_all(&src)?;
+    std::fs::write(src.join(OsStr::from_bytes(b"f\xfe.ext")), "f")?;
+    let dst = tmp_dir.path().join(OsStr::from_bytes(b"dst\xfd"));
+    esycpy()
+        .arg("copy")
+        .arg(src.join("*.ext"))
+        .arg(&dst)
+        .assert()
+        .success();
+    assert!(dst.join(OsStr::from_bytes(b"f\xfe.ext")).exists());
+    Ok(())
+}
+
+#[test]
+fn undo_move_cmdline() -> Result<()> {
+    let tmp_dir = TempDir::new().unwrap();
+    let journal_dir = tmp_dir.path().join("journal");
+    let src = tmp_dir.path().join("src");
+    std::fs::create_dir_all(src.join("sub"))?;
+    std::fs::write(src.join("sub").join("a.txt"), "a")?;
+    let dst = tmp_dir.path().join("out").join("dst");
+    esycpy()
+        .args(["move", src.to_str().unwrap(), dst.to_str().unwrap()])
+        .arg("--journal-dir")
+        .arg(&journal_dir)
+        .assert()
+        .success();
+    assert!(dst.join("sub").join("a.txt").exists());
+    assert!(!src.exists());
+    let out = esycpy()
+        .arg("undo")
+        .arg("--journal-dir")
+        .arg(&journal_dir)
+        .output()?;
+    assert!(out.status.success());
+    assert!(String::from_utf8(out.stdout)?.starts_with("reverted run "));
+    assert_eq!(std::fs::read_to_string(src.join("sub").join("a.txt"))?, "a");
+    assert!(!tmp_dir.path().join("out").exists());
+    esycpy()
+        .arg("undo")
+        .arg("--journal-dir")
+        .arg(&journal_dir)
+        .assert()
+        .failure();
+    Ok(())
+}
+
+#[test]
+fn journal_by_default_cmdline() -> Result<()> {
+    let tmp_dir = TempDir::new().unwrap();
+    let journal_dir = tmp_dir.path().join("journal");
+    let src = tmp_dir.path().join("a.txt");
+    std::fs::write(&src, "a")?;
+    let copy = |journal: bool| {
+        let mut cmd = esycpy();
+        cmd.env("ESYCPY_JOURNAL_DIR", &journal_dir)
+            .arg("copy")
+            .arg(&src)
+            .arg(tmp_dir.path().join("out/"));
+        if !journal {
+            cmd.arg("--no-journal");
+        }
+        cmd.assert().success();
+    };
+    copy(true);
+    assert_eq!(std::fs::read_dir(&journal_dir)?.count(), 1);
+    copy(false);
+    assert_eq!(std::fs::read_dir(&journal_dir)?.count(), 1);
+    Ok(())
+}
+
+#[test]
+fn resume_json_cmdline() -> Result<()> {
+    let tmp_dir = TempDir::new().unwrap();
+    let root = tmp_dir.path();
+    std::fs::write(root.join("a.txt"), "a")?;
+    let json_file = root.join("jobs.json");
+    std::fs::write(
+        &json_file,
+        r#"{
+            "variables_in_use": [],
+            "defaults": { "todo": "copy", "on_empty": "error" },
+            "jobs": [
+                { "src": "a.txt", "dst": "out/a.txt" },
+                { "src": "b.txt", "dst": "out/b.txt" }
+            ]
+        }"#,
+    )?;
+    let run = |resume: bool| -> Result<bool> {
+        let mut cmd = esycpy();
+        cmd.arg("-j").arg(&json_file);
+        if resume {
+            cmd.arg("--resume");
+        }
+        Ok(cmd.output()?.status.success())
+    };
+    // b.txt is missing, so the second job fails. its state is kept either way
+    assert!(!run(false)?);
+    assert!(root.join(".jobs.json.esycpy-state").exists());
+    // without --resume the state isn't read, the done job runs again
+    std::fs::remove_file(root.join("out").join("a.txt"))?;
+    assert!(!run(false)?);
+    assert!(root.join("out").join("a.txt").exists());
+    std::fs::remove_file(root.join("out").join("a.txt"))?;
+    std::fs::write(root.join("b.txt"), "b")?;
+    assert!(run(true)?);
+    assert!(!root.join("out").join("a.txt").exists());
+    assert!(root.join("out").join("b.txt").exists());
+    assert!(!root.join(".jobs.json.esycpy-state").exists());
+    Ok(())
+}
+
+#[test]
+fn events_ndjson_cmdline() -> Result<()> {
+    let tmp_dir = TempDir::new().unwrap();
+    let root = tmp_dir.path();
+    std::fs::write(root.join("a.txt"), "aaa")?;
+    std::fs::write(root.join("b.txt"), "bb")?;
+    let out_dir = root.join("out");
+    std::fs::create_dir_all(&out_dir)?;
+    std::fs::write(out_dir.join("b.txt"), "old")?;
+    // second run overwrites both files
+    for (overwritten, flags) in [["--events", "ndjson"], ["--output", "json"]]
+        .iter()
+        .enumerate()
+    {
+        let out = esycpy()
+            .arg("copy")
+            .args(flags)
+            .arg(root.join("*.txt"))
+            .arg(&out_dir)
+            .args(["--hash", "sha256"])
+            .output()?;
+        assert!(out.status.success());
+        let events: Vec<serde_json::Value> = String::from_utf8(out.stdout)?
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        let names: Vec<&str> = events
+            .iter()
+            .map(|e| e["event"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "job_started",
+                "file_planned",
+                "file_planned",
+                "file_transferred",
+                "file_transferred",
+                "job_finished",
+                "run_summary"
+            ]
+        );
+        assert_eq!(events[0]["op"], "copy");
+        assert_eq!(events[0]["job"], 0);
+        assert_eq!(events[6]["copied"], 2);
+        assert_eq!(events[6]["overwritten"], overwritten + 1);
+        assert_eq!(events[6]["bytes"], 5);
+    }
+    Ok(())
+}
+
+#[test]
+fn report_json_cmdline() -> Result<()> {
+    let tmp_dir = TempDir::new().unwrap();
+    let root = tmp_dir.path();
+    std::fs::create_dir_all(root.join("src"))?;
+    std::fs::write(root.join("src").join("a.txt"), "aaa")?;
+    std::fs::write(root.join("src").join("b.txt"), "bb")?;
+    let report = root.join("report.json");
+    esycpy()
+        .args(["hardlink", "--report"])
+        .arg(&report)
+        .arg(root.join("src"))
+        .arg(root.join("dst"))
+        .assert()
+        .success();
+    let report: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(report)?)?;
+    assert_eq!(report["jobs"][0]["op"], "hardlink");
+    assert_eq!(report["jobs"][0]["matched"], 2);
+    assert_eq!(report["total"]["linked"], 2);
+    assert_eq!(report["total"]["bytes"], 5);
+    assert_eq!(report["total"]["failed"], 0);
+    assert_eq!(report["total"]["slowest"].as_array().unwrap().len(), 2);
+    Ok(())
+}
+
+#[test]
+fn on_empty_cmdline() -> Result<()> {
+    let tmp_dir = TempDir::new().unwrap();
+    let src = tmp_dir.path().join("asets").join("*.xml");
+    let dst = tmp_dir.path().join("out");
+    esycpy()
+        .args(["copy", "--on-empty", "ok"])
+        .arg(&src)
+        .arg(&dst)
+        .assert()
+        .success();
+    let out = esycpy()
+        .args(["copy", "--on-empty", "error"])
+        .arg(&src)
+        .arg(&dst)
+        .output()?;
+    assert!(!out.status.success());
+    assert!(String::from_utf8(out.stderr)?.contains("no files matched"));
+    Ok(())
+}
+
+#[test]
+fn log_file_cmdline() -> Result<()> {
+    let tmp_dir = TempDir::new().unwrap();
+    let src = tmp_dir.path().join("a.txt");
+    std::fs::write(&src, "a")?;
+    let log = tmp_dir.path().join("logs").join("esycpy.log");
+    esycpy()
+        .args(["copy", "-q", "--log-format", "json", "--log-file"])
+        .arg(&log)
+        .arg(&src)
+        .arg(tmp_dir.path().join("out"))
+        .assert()
+        .success()
+        .stderr("");
+    let text = std::fs::read_to_string(&log)?;
+    assert!(text.contains("Running esycpy"));
+    for line in text.lines() {
+        let line: serde_json::Value = serde_json::from_str(line)?;
+        assert!(line["level"].is_string());
+    }
+    // a log file which can't be opened doesn't fail the run
+    esycpy()
+        .args(["copy", "--log-file"])
+        .arg(src.join("esycpy.log"))
+        .arg(&src)
+        .arg(tmp_dir.path().join("out2"))
+        .assert()
+        .success();
+    Ok(())
+}
+
+#[test]
+fn depfile_stamp_cmdline() -> Result<()> {
+    let tmp_dir = TempDir::new().unwrap();
+    let root = tmp_dir.path().canonicalize()?;
+    std::fs::create_dir_all(root.join("assets"))?;
+    std::fs::write(root.join("assets").join("a.png"), "a")?;
+    let json_file = root.join("jobs.json");
+    std::fs::write(
+        &json_file,
+        r#"{
+            "variables_in_use": [],
+            "jobs": [{ "todo": "copy", "src": "assets/*.png", "dst": "out" }]
+        }"#,
+    )?;
+    let depfile = root.join("build").join("assets.d");
+    let stamp = root.join("build").join("assets.stamp");
+    esycpy()
+        .arg("-j")
+        .arg(&json_file)
+        .arg("--depfile")
+        .arg(&depfile)
+        .arg("--stamp")
+        .arg(&stamp)
+        .assert()
+        .success();
+    assert!(root.join("out").join("a.png").is_file());
+    assert!(stamp.is_file());
+    let deps = std::fs::read_to_string(&depfile)?;
+    let deps: Vec<&str> = deps.split(" \\\n  ").map(str::trim).collect();
+    assert_eq!(
+        deps,
+        [
+            format!("{}:", stamp.display()),
+            json_file.display().to_string(),
+            root.join("assets").display().to_string(),
+            root.join("assets").join("a.png").display().to_string(),
+        ]
+    );
+    Ok(())
+}